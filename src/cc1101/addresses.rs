@@ -148,6 +148,11 @@ impl Register for FREQEST {
     const SIZE_BYTES: usize = 1;
 }
 
+impl Register for LQI {
+    const ADDRESS: u8 = 0x33;
+    const SIZE_BYTES: usize = 1;
+}
+
 impl Register for RSSI {
     const ADDRESS: u8 = 0x34;
     const SIZE_BYTES: usize = 1;
@@ -0,0 +1,62 @@
+use super::registers::FREQSYNTHCAL;
+
+/// 0x3E: PATABLE – PA output power table, written in burst
+pub const PATABLE_ADDRESS: u8 = 0x3E;
+
+/// Frequency bands the CC1101 supports, each with its own path through the
+/// Flipper's RF switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Band {
+    /// 300 - 348 MHz
+    Mhz315,
+    /// 387 - 464 MHz
+    Mhz433,
+    /// 779 - 902 MHz
+    Mhz868,
+    /// 902 - 928 MHz, sharing the 868 MHz path
+    Mhz915,
+}
+
+impl Band {
+    /// Finds the band covering `freq_hz`, or `None` if the CC1101 can't tune
+    /// there. Limits are from datasheet table 2.
+    pub fn for_frequency(freq_hz: u32) -> Option<Self> {
+        match freq_hz {
+            300_000_000..=348_000_000 => Some(Band::Mhz315),
+            387_000_000..=464_000_000 => Some(Band::Mhz433),
+            779_000_000..=901_999_999 => Some(Band::Mhz868),
+            902_000_000..=928_000_000 => Some(Band::Mhz915),
+            _ => None,
+        }
+    }
+
+    /// Levels for `gpio_rf_sw_0` and GDO2, which together drive the RF switch.
+    /// See `furi_hal_subghz_set_path` in the Flipper firmware.
+    pub fn rf_switch(&self) -> (bool, bool) {
+        match self {
+            Band::Mhz315 => (true, false),
+            Band::Mhz433 => (false, true),
+            Band::Mhz868 | Band::Mhz915 => (true, true),
+        }
+    }
+
+    /// FSCAL3..0 from SmartRF Studio, a starting point until SCAL runs.
+    pub fn fscal_defaults(&self) -> FREQSYNTHCAL {
+        match self {
+            Band::Mhz315 | Band::Mhz433 => FREQSYNTHCAL::from_bytes([0xE9, 0x2A, 0x00, 0x1F]),
+            Band::Mhz868 | Band::Mhz915 => FREQSYNTHCAL::from_bytes([0xEA, 0x2A, 0x00, 0x1F]),
+        }
+    }
+
+    /// PATABLE for 0 dBm out, from TI design note DN013. Only the first entry
+    /// is used for FSK.
+    pub fn patable(&self) -> [u8; 8] {
+        let power = match self {
+            Band::Mhz315 => 0x51,
+            Band::Mhz433 => 0x60,
+            Band::Mhz868 => 0x50,
+            Band::Mhz915 => 0x8E,
+        };
+        [power, 0, 0, 0, 0, 0, 0, 0]
+    }
+}
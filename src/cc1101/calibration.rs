@@ -0,0 +1,135 @@
+use core::fmt::{self, Write};
+
+use heapless::Vec;
+
+/// Calibrations kept at once, enough for every protocol across a few
+/// temperature buckets.
+pub const FSCAL_CACHE_LEN: usize = 16;
+/// Width of a temperature bucket. The datasheet recommends recalibrating after
+/// large temperature swings, so one calibration serves a whole bucket.
+pub const TEMP_BUCKET_C: i32 = 10;
+
+/// What a synthesizer calibration depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FscalKey {
+    pub frequency_hz: u32,
+    pub temp_bucket: i8,
+}
+
+impl FscalKey {
+    pub fn new(frequency_hz: u32, temp_c: f32) -> Self {
+        Self {
+            frequency_hz,
+            temp_bucket: (temp_c as i32).div_euclid(TEMP_BUCKET_C) as i8,
+        }
+    }
+}
+
+/// FSCAL3..1 as SCAL leaves them, keyed on frequency and temperature, so
+/// switching back to a frequency restores its calibration instead of running
+/// SCAL again.
+#[derive(Debug, Clone, Default)]
+pub struct FscalCache {
+    entries: Vec<(FscalKey, [u8; 3]), FSCAL_CACHE_LEN>,
+    dirty: bool,
+}
+
+impl FscalCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn get(&self, key: FscalKey) -> Option<[u8; 3]> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| *entry == key)
+            .map(|&(_, fscal)| fscal)
+    }
+
+    /// Stores a calibration, dropping the oldest when full.
+    pub fn insert(&mut self, key: FscalKey, fscal: [u8; 3]) {
+        self.entries.retain(|(entry, _)| *entry != key);
+        if self.entries.is_full() {
+            self.entries.remove(0);
+        }
+        let _ = self.entries.push((key, fscal));
+        self.dirty = true;
+    }
+
+    /// Whether calibrations were added since the cache was loaded.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Restores calibrations from lines of `fscal <freq_hz> <bucket> <FSCAL3..1 hex>`.
+    pub fn load(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut tokens = line.split_whitespace();
+            if tokens.next() != Some("fscal") {
+                continue;
+            }
+            let (Some(frequency_hz), Some(temp_bucket), Some(fscal)) = (
+                tokens.next().and_then(|token| token.parse().ok()),
+                tokens.next().and_then(|token| token.parse().ok()),
+                tokens
+                    .next()
+                    .and_then(|token| u32::from_str_radix(token, 16).ok()),
+            ) else {
+                continue;
+            };
+            let [_, fscal3, fscal2, fscal1] = fscal.to_be_bytes();
+            let key = FscalKey {
+                frequency_hz,
+                temp_bucket,
+            };
+            self.insert(key, [fscal3, fscal2, fscal1]);
+        }
+        self.dirty = false;
+    }
+
+    pub fn save(&self, out: &mut impl Write) -> fmt::Result {
+        for (key, [fscal3, fscal2, fscal1]) in &self.entries {
+            writeln!(
+                out,
+                "fscal {} {} {:02X}{:02X}{:02X}",
+                key.frequency_hz, key.temp_bucket, fscal3, fscal2, fscal1
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::String;
+
+    #[test]
+    fn buckets_temperatures() {
+        assert_eq!(FscalKey::new(433_920_000, 24.5).temp_bucket, 2);
+        assert_eq!(FscalKey::new(433_920_000, 29.9).temp_bucket, 2);
+        assert_eq!(FscalKey::new(433_920_000, -15.0).temp_bucket, -2);
+    }
+
+    #[test]
+    fn round_trips_calibrations() {
+        let mut cache = FscalCache::new();
+        let key = FscalKey::new(433_920_000, 22.0);
+        cache.insert(key, [0xE9, 0x2A, 0x00]);
+        cache.insert(key, [0xEA, 0x2A, 0x17]);
+        assert!(cache.is_dirty());
+
+        let mut text: String<128> = String::new();
+        cache.save(&mut text).unwrap();
+        assert_eq!(text.as_str(), "fscal 433920000 2 EA2A17\n");
+
+        let mut restored = FscalCache::new();
+        restored.load(&text);
+        assert_eq!(restored.get(key), Some([0xEA, 0x2A, 0x17]));
+        assert_eq!(restored.get(FscalKey::new(868_300_000, 22.0)), None);
+        assert!(!restored.is_dirty());
+    }
+}
@@ -1,6 +1,8 @@
 use modular_bitfield::prelude::*;
 
 pub const F_XOSC: f32 = 26_000_000.0;
+/// Typical RSSI offset, from datasheet table 31
+pub const RSSI_OFFSET_DB: f32 = 74.0;
 
 // Enums for option fields
 #[repr(u8)]
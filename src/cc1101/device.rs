@@ -2,22 +2,38 @@ use core::{
     any::Any,
     ffi::CStr,
     fmt::{Debug, Write},
+    ops::RangeInclusive,
 };
 use flipperzero::{debug, error, format, info, println};
 use flipperzero_sys::{
-    furi_hal_gpio_init, furi_hal_gpio_write, furi_hal_spi_acquire, furi_hal_spi_bus_trx,
-    furi_hal_spi_release, gpio_rf_sw_0, subghz_devices_begin, subghz_devices_deinit,
-    subghz_devices_end, subghz_devices_get_by_name, subghz_devices_get_data_gpio,
-    subghz_devices_init, FuriHalSpiBusHandle, GpioModeAnalog, GpioModeInput,
-    GpioModeOutputPushPull, GpioPin, GpioPullNo, GpioSpeedLow, SubGhzDeviceCC1101Int,
+    furi_delay_tick, furi_hal_gpio_add_int_callback, furi_hal_gpio_init, furi_hal_gpio_read,
+    furi_hal_gpio_remove_int_callback, furi_hal_gpio_write, furi_hal_power_get_battery_temperature,
+    furi_hal_spi_acquire, furi_hal_spi_bus_trx, furi_hal_spi_release, furi_semaphore_acquire,
+    furi_semaphore_alloc, furi_semaphore_free, furi_semaphore_release, gpio_rf_sw_0,
+    subghz_devices_begin, subghz_devices_deinit, subghz_devices_end, subghz_devices_get_by_name,
+    subghz_devices_get_data_gpio, subghz_devices_init, subghz_devices_start_async_rx,
+    subghz_devices_stop_async_rx, FuriHalPowerICFuelGauge, FuriHalSpiBusHandle, FuriSemaphore,
+    FuriStatusOk, GpioModeAnalog, GpioModeInput, GpioModeInterruptRise, GpioModeOutputPushPull,
+    GpioPin, GpioPullNo, GpioSpeedLow, SubGhzDeviceCC1101Int,
 };
-use heapless::String;
+use heapless::{String, Vec};
 use modular_bitfield::prelude::*;
 
 use crate::cc1101::{
-    addresses::Register, constants::*, logging::REGISTER_DUMP_BUFFER_SIZE, registers::*,
+    addresses::Register, band::*, calibration::*, constants::*, logging::REGISTER_DUMP_BUFFER_SIZE,
+    registers::*, wor::WorConfig,
 };
 const MAX_SPI_BUF: usize = 65;
+/// Longest to wait for SCAL to finish.
+const SCAL_TIMEOUT_TICKS: u32 = 5;
+/// MARCSTATE value once the radio is back in IDLE.
+const MARCSTATE_IDLE: u8 = 0x01;
+/// Frequencies to remember a transmitter offset for.
+const MAX_FREQ_OFFSETS: usize = 8;
+/// MCSM2.RX_TIME that stays in RX until a packet arrives, the reset value.
+const RX_TIME_UNTIL_PACKET: u8 = 7;
+/// Status registers, read with the burst bit that otherwise selects a strobe.
+const STATUS_ADDRESSES: RangeInclusive<u8> = 0x30..=0x3D;
 static SUBGHZ_DEVICE_CC1101_INT_NAME: &CStr = c"cc1101_int";
 
 /// Represents the full CC1101 register map in RAM.
@@ -52,6 +68,7 @@ pub struct CC1101Device {
     pub partnum: PARTNUM,
     pub version: VERSION,
     pub freq_est: FREQEST,
+    pub lqi: LQI,
     pub rssi: RSSI,
     pub marc_state: MARCSTATE,
     pub wor_time: WORTIME,
@@ -60,6 +77,18 @@ pub struct CC1101Device {
     pub tx_bytes: TXBYTES,
     pub rx_bytes: RXBYTES,
     pub rc_ctrl_status: RCCTRL_STATUS,
+    /// Band the RF switch is routed for.
+    pub band: Band,
+    pub frequency_hz: u32,
+    pub fscal_cache: FscalCache,
+    /// What the synthesizer was last calibrated for, if anything.
+    calibrated_for: Option<FscalKey>,
+    /// FSCTRL0 offsets set for each frequency, reapplied when tuning back.
+    freq_offsets: Vec<(u32, i8), MAX_FREQ_OFFSETS>,
+    /// Set while RX is entered through Wake-on-Radio.
+    wor: Option<WorConfig>,
+    /// Set between `sleep` and `wake`.
+    asleep: bool,
 }
 
 impl CC1101Device {
@@ -112,6 +141,7 @@ impl CC1101Device {
             partnum: PARTNUM::new(),
             version: VERSION::new(),
             freq_est: FREQEST::new(),
+            lqi: LQI::new(),
             rssi: RSSI::new(),
             marc_state: MARCSTATE::new(),
             wor_time: WORTIME::new(),
@@ -120,6 +150,13 @@ impl CC1101Device {
             tx_bytes: TXBYTES::new(),
             rx_bytes: RXBYTES::new(),
             rc_ctrl_status: RCCTRL_STATUS::new(),
+            band: Band::Mhz433,
+            frequency_hz: 0,
+            fscal_cache: FscalCache::new(),
+            calibrated_for: None,
+            freq_offsets: Vec::new(),
+            wor: None,
+            asleep: false,
         };
 
         // Reset the radio
@@ -136,7 +173,7 @@ impl CC1101Device {
             .gdo_config
             .set_gdo1_cfg(GDO_PIN_CONFIG::HighImpedance);
 
-        // Set up RF Switch to 300 - 348MHz path permanently
+        // Set up the RF switch, starting on the 433MHz path
         // See https://github.com/flipperdevices/flipperzero-firmware/blob/c9ab2b6827fc4d646e98ad0fc15a264240b58986/targets/f7/furi_hal/furi_hal_subghz.c#L348
         // for settings
         unsafe {
@@ -146,18 +183,281 @@ impl CC1101Device {
                 GpioPullNo,
                 GpioSpeedLow,
             );
-            furi_hal_gpio_write(&gpio_rf_sw_0, false);
         }
-        // Write to device
-        new_self
-            .gdo_config
-            .set_gdo2_cfg(GDO_PIN_CONFIG::HardwareZero);
-        new_self.gdo_config.set_gdo2_inv(true);
-        new_self.write_register(new_self.gdo_config);
+        new_self.set_band(Band::Mhz433);
+
+        // Calibration is restored from the cache or run by ensure_calibrated
+        new_self.mcsm.set_fs_autocal(FS_AUTOCAL::NEVER);
+        new_self.write_register(new_self.mcsm);
 
         return new_self;
     }
 
+    /// Routes the RF switch for `band` and loads its FSCAL and PATABLE defaults.
+    pub fn set_band(&mut self, band: Band) {
+        let (rf_sw_0, gdo2_inv) = band.rf_switch();
+        unsafe {
+            furi_hal_gpio_write(&gpio_rf_sw_0, rf_sw_0);
+        }
+        self.gdo_config.set_gdo2_cfg(GDO_PIN_CONFIG::HardwareZero);
+        self.gdo_config.set_gdo2_inv(gdo2_inv);
+        self.write_register(self.gdo_config);
+
+        self.freq_synth_cal = band.fscal_defaults();
+        self.write_register(self.freq_synth_cal);
+        self.spi_write_burst(PATABLE_ADDRESS, &band.patable());
+        self.band = band;
+        self.calibrated_for = None;
+    }
+
+    /// Tunes to `freq_hz`, switching band first if needed. Returns `None`
+    /// without touching the radio if the CC1101 can't receive there.
+    pub fn set_frequency(&mut self, freq_hz: u32) -> Option<Band> {
+        let band = Band::for_frequency(freq_hz)?;
+        if band != self.band {
+            self.set_band(band);
+        }
+        self.freq_ctrl.set_freq_mhz(freq_hz as f32 / 1_000_000f32);
+        self.write_register(self.freq_ctrl);
+        self.frequency_hz = freq_hz;
+
+        let offset = self
+            .freq_offsets
+            .iter()
+            .find(|&&(freq, _)| freq == freq_hz)
+            .map_or(0, |&(_, offset)| offset);
+        self.freq_synth_ctrl.set_freqoff(offset as u8);
+        self.write_register(self.freq_synth_ctrl);
+        Some(band)
+    }
+
+    /// The demodulator's estimate of the last packet's carrier offset from the
+    /// tuned frequency, in steps of f_xosc / 2^14 (about 1.6 kHz).
+    pub fn read_freq_estimate(&mut self) -> i8 {
+        self.sync_field(|dev| &mut dev.freq_est);
+        self.freq_est.freqoff_est() as i8
+    }
+
+    /// The FSCTRL0 offset currently applied, in the same steps as FREQEST.
+    pub fn freq_offset(&self) -> i8 {
+        self.freq_synth_ctrl.freqoff() as i8
+    }
+
+    /// Shifts the synthesizer by `offset` steps to centre the channel filter on
+    /// a transmitter, remembering it for the current frequency.
+    pub fn set_freq_offset(&mut self, offset: i8) {
+        self.freq_synth_ctrl.set_freqoff(offset as u8);
+        self.write_register(self.freq_synth_ctrl);
+
+        let frequency_hz = self.frequency_hz;
+        self.freq_offsets.retain(|&(freq, _)| freq != frequency_hz);
+        if self.freq_offsets.is_full() {
+            self.freq_offsets.remove(0);
+        }
+        let _ = self.freq_offsets.push((frequency_hz, offset));
+    }
+
+    /// Makes sure the synthesizer is calibrated for the current frequency and
+    /// temperature, restoring a cached calibration if there is one and running
+    /// SCAL otherwise. Call from IDLE.
+    pub fn ensure_calibrated(&mut self) {
+        let key = self.calibration_key();
+        if self.calibrated_for == Some(key) {
+            return;
+        }
+
+        match self.fscal_cache.get(key) {
+            Some(fscal) => {
+                self.restore_fscal(fscal);
+                self.calibrated_for = Some(key);
+            }
+            None => self.calibrate_now(),
+        }
+    }
+
+    fn calibration_key(&self) -> FscalKey {
+        let temp_c = unsafe { furi_hal_power_get_battery_temperature(FuriHalPowerICFuelGauge) };
+        FscalKey::new(self.frequency_hz, temp_c)
+    }
+
+    /// Runs SCAL regardless of the cache, and caches the result.
+    fn calibrate_now(&mut self) {
+        let key = self.calibration_key();
+        let fscal = self.calibrate();
+        debug!("Calibrated {} Hz: {:?}", self.frequency_hz, fscal);
+        self.fscal_cache.insert(key, fscal);
+        self.calibrated_for = Some(key);
+    }
+
+    /// Runs SCAL and returns the resulting FSCAL3..1.
+    fn calibrate(&mut self) -> [u8; 3] {
+        self.spi_send_command(CMD::SCAL);
+        // Calibration takes about 720 us, after which the radio returns to IDLE
+        for _ in 0..SCAL_TIMEOUT_TICKS {
+            unsafe { furi_delay_tick(1) };
+            self.sync_field(|dev| &mut dev.marc_state);
+            if self.marc_state.marcstate() == MARCSTATE_IDLE {
+                break;
+            }
+        }
+        self.sync_field(|dev| &mut dev.freq_synth_cal);
+        let [fscal3, fscal2, fscal1, _] = self.freq_synth_cal.into_bytes();
+        [fscal3, fscal2, fscal1]
+    }
+
+    fn restore_fscal(&mut self, [fscal3, fscal2, fscal1]: [u8; 3]) {
+        let [_, _, _, fscal0] = self.freq_synth_cal.into_bytes();
+        self.freq_synth_cal = FREQSYNTHCAL::from_bytes([fscal3, fscal2, fscal1, fscal0]);
+        self.write_register(self.freq_synth_cal);
+    }
+
+    /// Switches to Wake-on-Radio: the radio sleeps on its RC oscillator and
+    /// wakes every EVENT0 to listen for `config.rx_time`, staying in RX only if
+    /// carrier sense or a sync word turns up. `start_rx` then enters WOR
+    /// instead of RX. Call from IDLE.
+    pub fn enable_wor(&mut self, config: WorConfig) {
+        self.wor_evt.set_timeout(config.event0);
+        self.write_register(self.wor_evt);
+
+        self.wor_ctrl.set_wor_res(0);
+        self.wor_ctrl.set_event1(config.event1);
+        self.wor_ctrl.set_rc_pd(false);
+        self.wor_ctrl.set_rc_cal(true);
+        self.write_register(self.wor_ctrl);
+        self.calibrate_rc_oscillator();
+
+        self.mcsm.set_rx_time(config.rx_time);
+        self.mcsm.set_rx_time_rssi(true);
+        self.mcsm.set_rx_time_qual(true);
+        self.write_register(self.mcsm);
+        self.wor = Some(config);
+    }
+
+    /// Returns to continuous RX and powers down the RC oscillator.
+    pub fn disable_wor(&mut self) {
+        if self.wor.take().is_none() {
+            return;
+        }
+        self.spi_send_command(CMD::SIDLE);
+        self.wor_ctrl.set_rc_cal(false);
+        self.wor_ctrl.set_rc_pd(true);
+        self.write_register(self.wor_ctrl);
+
+        self.mcsm.set_rx_time(RX_TIME_UNTIL_PACKET);
+        self.mcsm.set_rx_time_rssi(false);
+        self.mcsm.set_rx_time_qual(false);
+        self.write_register(self.mcsm);
+    }
+
+    /// The RC oscillator calibrates alongside the synthesizer while RC_CAL is
+    /// set, so this runs SCAL even if the cache has the frequency. RC_CAL
+    /// stays set, so the radio keeps its own result and RCCTRL goes unused.
+    fn calibrate_rc_oscillator(&mut self) {
+        self.calibrate_now();
+        self.sync_field(|dev| &mut dev.rc_ctrl_status);
+        debug!(
+            "RC oscillator calibrated: {:02X} {:02X}",
+            self.rc_ctrl_status.rcctrl1_status(),
+            self.rc_ctrl_status.rcctrl0_status()
+        );
+    }
+
+    /// Flushes the RX FIFO and starts listening, through WOR if it's enabled.
+    /// Call from IDLE.
+    pub fn start_rx(&mut self) {
+        self.ensure_calibrated();
+        self.spi_send_command(CMD::SFRX);
+        match self.wor {
+            Some(_) => {
+                // TEST0..2 are lost in SLEEP, so restore them before every WOR
+                self.write_register(self.test_settings);
+                self.spi_send_command(CMD::SWORRST);
+                self.spi_send_command(CMD::SWOR);
+            }
+            None => {
+                self.spi_send_command(CMD::SRX);
+            }
+        }
+    }
+
+    /// Powers the radio down to SLEEP, where it keeps its configuration but
+    /// stops its crystal, until `wake`. Call from IDLE. Does nothing if the
+    /// radio is already asleep.
+    pub fn sleep(&mut self) {
+        if !self.asleep {
+            self.spi_send_command(CMD::SPWD);
+            self.asleep = true;
+        }
+    }
+
+    /// Brings the radio back to IDLE after `sleep`, waiting for the crystal to
+    /// start and restoring the TEST registers SLEEP loses. Does nothing if the
+    /// radio is awake.
+    pub fn wake(&mut self) {
+        if !core::mem::take(&mut self.asleep) {
+            return;
+        }
+        unsafe {
+            // Pulling CSn low wakes the radio, and SO goes low once it's ready
+            furi_hal_spi_acquire(self.handle);
+            while furi_hal_gpio_read((*self.handle).miso) {}
+            furi_hal_spi_release(self.handle);
+        }
+        self.write_register(self.test_settings);
+    }
+
+    /// Sleeps until GDO0 rises or `timeout_ticks` pass, without polling.
+    /// Returns whether GDO0 is asserted.
+    pub fn wait_for_gdo0(&self, timeout_ticks: u32) -> bool {
+        unsafe extern "C" fn wake(ctx: *mut core::ffi::c_void) {
+            furi_semaphore_release(ctx as *mut FuriSemaphore);
+        }
+
+        unsafe {
+            if furi_hal_gpio_read(self.subghz_gdo0) {
+                return true;
+            }
+            let semaphore = furi_semaphore_alloc(1, 0);
+            furi_hal_gpio_init(
+                self.subghz_gdo0,
+                GpioModeInterruptRise,
+                GpioPullNo,
+                GpioSpeedLow,
+            );
+            furi_hal_gpio_add_int_callback(self.subghz_gdo0, Some(wake), semaphore.cast());
+            // GDO0 may have risen before the callback was in place
+            let woke = furi_hal_gpio_read(self.subghz_gdo0)
+                || furi_semaphore_acquire(semaphore, timeout_ticks) == FuriStatusOk;
+
+            furi_hal_gpio_remove_int_callback(self.subghz_gdo0);
+            furi_hal_gpio_init(self.subghz_gdo0, GpioModeInput, GpioPullNo, GpioSpeedLow);
+            furi_semaphore_free(semaphore);
+            woke
+        }
+    }
+
+    /// Times GDO0 pulses with TIM2 and enters RX, for asynchronous serial mode.
+    /// `callback` runs in interrupt context with each pulse's level and length
+    /// in microseconds.
+    pub fn start_async_rx(
+        &mut self,
+        callback: unsafe extern "C" fn(bool, u32, *mut core::ffi::c_void),
+        context: *mut core::ffi::c_void,
+    ) {
+        self.ensure_calibrated();
+        self.spi_send_command(CMD::SFRX);
+        unsafe { subghz_devices_start_async_rx(self.subghz, callback as *mut _, context) };
+    }
+
+    /// Stops pulse timing and idles the radio, handing GDO0 back as an input.
+    pub fn stop_async_rx(&mut self) {
+        unsafe {
+            subghz_devices_stop_async_rx(self.subghz);
+            furi_hal_gpio_init(self.subghz_gdo0, GpioModeInput, GpioPullNo, GpioSpeedLow);
+        }
+        self.spi_send_command(CMD::SIDLE);
+    }
+
     pub fn spi_send_command(&self, command: CMD) -> u8 {
         let spi_tx: u8 = command as u8;
         let mut spi_rx: u8 = 0x00;
@@ -232,7 +532,15 @@ impl CC1101Device {
 
     pub fn read_register<const S: usize, T: Register + From<[u8; S]>>(&self) -> T {
         let mut raw = [0u8; S];
-        self.spi_read_burst(T::ADDRESS, &mut raw);
+        if STATUS_ADDRESSES.contains(&T::ADDRESS) {
+            // Status registers have no burst access, so read them a byte at a time
+            for (offset, byte) in raw.iter_mut().enumerate() {
+                let address = T::ADDRESS + offset as u8;
+                self.spi_read_burst(address, core::slice::from_mut(byte));
+            }
+        } else {
+            self.spi_read_burst(T::ADDRESS, &mut raw);
+        }
         T::from(raw)
     }
 
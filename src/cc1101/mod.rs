@@ -3,11 +3,17 @@
 pub mod constants;
 pub mod registers;
 pub mod addresses;
+pub mod band;
+pub mod calibration;
 pub mod logging;
+pub mod wor;
 pub mod device;
 
 // Re-export everything for convenience
 pub use constants::*;
 pub use registers::*;
 pub use addresses::*;
+pub use band::*;
+pub use calibration::*;
+pub use wor::*;
 pub use device::*;
@@ -346,6 +346,18 @@ pub struct WOREVT {
     pub event0: u16,
 }
 
+impl WOREVT {
+    /// Sets EVENT0. WOREVT1 holds the high byte at the lower address, the
+    /// opposite of how the bitfield lays out a `u16`.
+    pub fn set_timeout(&mut self, event0: u16) {
+        self.set_event0(event0.swap_bytes());
+    }
+
+    pub fn timeout(&self) -> u16 {
+        self.event0().swap_bytes()
+    }
+}
+
 
 /// 0x20: WORCTRL – Wake On Radio Control
 #[bitfield]
@@ -490,6 +502,14 @@ pub struct RSSI {
     pub rssi: u8,
 }
 
+/// Helper function to convert the RSSI reading to dBm
+impl RSSI {
+    pub fn rssi_dbm(&self) -> f32 {
+        // RSSI is a two's complement value in 0.5dB steps
+        (self.rssi() as i8) as f32 / 2.0 - RSSI_OFFSET_DB
+    }
+}
+
 
 /// 0x35: MARCSTATE – Main Radio Control State Machine State
 #[bitfield]
@@ -514,6 +534,13 @@ pub struct PKTSTATUS {
     pub pktstatus: u8,
 }
 
+impl PKTSTATUS {
+    /// Bit 6: carrier sense, which GDO0 can't show in asynchronous serial mode.
+    pub fn carrier_sense(&self) -> bool {
+        self.pktstatus() & 0x40 != 0
+    }
+}
+
 
 /// 0x39: VCO_VC_DAC – Current Setting from PLL Calibration Module
 #[bitfield]
@@ -545,7 +572,7 @@ pub struct RXBYTES {
 #[bitfield]
 #[derive(Debug, Clone, Copy)]
 pub struct RCCTRL_STATUS {
-    pub rcctrl0_status: u8,
     pub rcctrl1_status: u8,
+    pub rcctrl0_status: u8,
 }
 
@@ -0,0 +1,136 @@
+use super::constants::{EVENT1, F_XOSC};
+
+/// Oscillator periods in one EVENT0 step with WOR_RES = 0, from datasheet
+/// section 19.5.
+const EVENT0_PERIODS: f32 = 750.0;
+/// Share of each WOR period spent in RX at RX_TIME = 0 with WOR_RES = 0.
+/// Every RX_TIME step above that halves it.
+const MAX_DUTY_CYCLE: f32 = 0.125;
+/// Largest RX_TIME that times out. 7 would stay in RX until a packet arrives.
+const MAX_RX_TIME: u8 = 6;
+/// Wake to RX delay in RC periods, enough for the crystal's 150 us start-up.
+const WAKE_EVENT1: EVENT1 = EVENT1::P6;
+const WAKE_EVENT1_PERIODS: f32 = 6.0;
+/// IDLE to RX without calibration, from datasheet table 34.
+const RX_SETTLE_MS: f32 = 0.075;
+
+/// EVENT0, EVENT1 and the RX timeout for Wake-on-Radio, where the radio
+/// sleeps on the RC oscillator and wakes every EVENT0 to listen briefly.
+#[derive(Debug, Clone, Copy)]
+pub struct WorConfig {
+    /// WOREVT, in units of 750 / f_xosc.
+    pub event0: u16,
+    /// Wake to RX delay.
+    pub event1: EVENT1,
+    /// MCSM2.RX_TIME, the RX window as a fraction of EVENT0.
+    pub rx_time: u8,
+}
+
+impl WorConfig {
+    /// Wakes often enough to hear a preamble that the decoder can join up to
+    /// `slack_us` late. Carrier sense may end a wake as soon as RSSI is valid,
+    /// so the whole period counts as deaf and `duty_cycle` only sets how long
+    /// a quiet wake may last. Returns `None` if no period that short still
+    /// fits the wake-up and the RX window.
+    ///
+    /// The datasheet asks for at least 11 ms of sleep, or Event 0 may come
+    /// early. Early wakes only shorten the gap, so shorter periods are kept.
+    pub fn for_preamble(slack_us: f32, duty_cycle: f32) -> Option<Self> {
+        let interval_ms = slack_us / 1000.0 - RX_SETTLE_MS;
+        // Round down, since a longer period would open a longer gap
+        let event0 = interval_ms / 1000.0 * F_XOSC / EVENT0_PERIODS;
+        if !(1.0..=u16::MAX as f32).contains(&event0) {
+            return None;
+        }
+        let config = Self {
+            event0: event0 as u16,
+            event1: WAKE_EVENT1,
+            rx_time: Self::rx_time_for(duty_cycle)?,
+        };
+        (config.wake_ms() + config.rx_window_ms() < config.interval_ms()).then_some(config)
+    }
+
+    fn rx_time_for(duty_cycle: f32) -> Option<u8> {
+        if !(duty_cycle > 0.0 && duty_cycle <= MAX_DUTY_CYCLE) {
+            return None;
+        }
+        // Each step halves the duty cycle, so compare ratios rather than differences
+        (0..=MAX_RX_TIME).min_by(|&a, &b| {
+            let off = |rx_time| {
+                let ratio = Self::duty_cycle_for(rx_time) / duty_cycle;
+                ratio.max(1.0 / ratio)
+            };
+            off(a).total_cmp(&off(b))
+        })
+    }
+
+    fn duty_cycle_for(rx_time: u8) -> f32 {
+        MAX_DUTY_CYCLE / (1u32 << rx_time) as f32
+    }
+
+    pub fn interval_ms(&self) -> f32 {
+        self.event0 as f32 * EVENT0_PERIODS / F_XOSC * 1000.0
+    }
+
+    /// The duty cycle actually achieved.
+    pub fn duty_cycle(&self) -> f32 {
+        Self::duty_cycle_for(self.rx_time)
+    }
+
+    /// How long each wake listens before going back to sleep, unless carrier
+    /// sense or a sync word keeps it in RX.
+    pub fn rx_window_ms(&self) -> f32 {
+        self.interval_ms() * self.duty_cycle()
+    }
+
+    /// Time from Event 0 until the radio is listening.
+    fn wake_ms(&self) -> f32 {
+        WAKE_EVENT1_PERIODS * EVENT0_PERIODS / F_XOSC * 1000.0 + RX_SETTLE_MS
+    }
+
+    /// Longest stretch of signal that can pass unheard, from a wake that
+    /// carrier sense ends at once to the next one listening.
+    pub fn longest_gap_ms(&self) -> f32 {
+        self.interval_ms() + RX_SETTLE_MS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::preamble_slack_us;
+    use crate::protocol::{Em422em, PowerProtocol};
+
+    #[test]
+    fn computes_event0_and_rx_time() {
+        let config = WorConfig::for_preamble(10_075.0, 0.01).unwrap();
+        assert_eq!(config.event0, 346);
+        // 0.78% is closer than 1.56% in ratio terms
+        assert_eq!(config.rx_time, 4);
+        assert!((config.interval_ms() - 9.981).abs() < 0.001);
+        assert!((config.rx_window_ms() - 0.078).abs() < 0.001);
+
+        assert_eq!(WorConfig::for_preamble(10_000.0, 0.125).unwrap().rx_time, 0);
+        let rarely = WorConfig::for_preamble(10_000.0, 0.0001).unwrap();
+        assert_eq!(rarely.rx_time, MAX_RX_TIME);
+    }
+
+    #[test]
+    fn rejects_untimeable_settings() {
+        assert!(WorConfig::for_preamble(3_000_000.0, 0.01).is_none());
+        assert!(WorConfig::for_preamble(1000.0, 0.25).is_none());
+        assert!(WorConfig::for_preamble(1000.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn hears_the_em422em_preamble() {
+        let slack_us = preamble_slack_us(Em422em.radio_profile().data_rate_baud);
+        let config = WorConfig::for_preamble(slack_us, MAX_DUTY_CYCLE).unwrap();
+        assert!(config.longest_gap_ms() * 1000.0 <= slack_us);
+        // But not needlessly often
+        assert!(config.longest_gap_ms() * 1000.0 > slack_us * 0.9);
+
+        // Too short for the radio to even wake up in
+        assert!(WorConfig::for_preamble(200.0, MAX_DUTY_CYCLE).is_none());
+    }
+}
@@ -0,0 +1,298 @@
+//! Decodes captures on a PC, for triaging field captures without a Flipper.
+//!
+//! Takes files, or stdin if none are given, holding any of:
+//! - Sub-GHz app RAW recordings (`.sub`)
+//! - lines from the capture log `record` writes
+//! - hex dumps, one capture per line
+//!
+//! Prints a line of JSON for every reading or failure. Blank lines and lines
+//! starting with `#` are skipped.
+
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+mod currentcost;
+mod decode;
+mod efergy;
+mod owl;
+mod protocol;
+mod pulse;
+mod subfile;
+
+/// Only the firmware uses these, but they are plain logic, so their tests run
+/// here.
+#[cfg(test)]
+mod fixtures;
+#[cfg(test)]
+mod learn;
+#[cfg(test)]
+mod recorder;
+#[cfg(test)]
+mod scan;
+#[cfg(test)]
+mod schedule;
+#[cfg(test)]
+mod sensors;
+#[cfg(test)]
+mod spectrum;
+
+/// The radio driver only builds for the Flipper, but these parts are plain
+/// arithmetic, so their tests run here. Most of the register map is only
+/// for the driver.
+#[cfg(test)]
+#[allow(
+    dead_code,
+    non_snake_case,
+    non_camel_case_types,
+    clippy::upper_case_acronyms
+)]
+mod cc1101 {
+    pub mod calibration;
+    pub mod constants;
+    pub mod wor;
+}
+
+use crate::decode::{DecodeError, DecodeResult};
+use crate::protocol::{Em422em, PowerProtocol, Reading, PROTOCOLS};
+use crate::pulse::Pulse;
+use crate::subfile::SubParser;
+
+/// Fields before the `:` in a capture log line, and which is the protocol.
+const RECORD_FIELDS: usize = 7;
+const RECORD_PROTOCOL_FIELD: usize = 5;
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    let mut status = ExitCode::SUCCESS;
+    if paths.is_empty() {
+        let mut text = String::new();
+        match io::stdin().read_to_string(&mut text) {
+            Ok(_) => decode_text("-", &text),
+            Err(e) => {
+                eprintln!("Failed to read stdin: {e}");
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+    for path in &paths {
+        match std::fs::read_to_string(path) {
+            Ok(text) => decode_text(path, &text),
+            Err(e) => {
+                eprintln!("Failed to read {path}: {e}");
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+    status
+}
+
+fn decode_text(path: &str, text: &str) {
+    if text.contains("Filetype: Flipper SubGhz") {
+        return decode_sub(path, text);
+    }
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let source = format!("{path}:{}", idx + 1);
+        match parse_line(line) {
+            Some((protocol, data)) => decode_bytes(&source, protocol, &data),
+            None => print_failure(&source, None, "invalid_line"),
+        }
+    }
+}
+
+/// Splits a capture log line or a hex dump into the protocol that captured
+/// it, if recorded, and its bytes.
+fn parse_line(line: &str) -> Option<(Option<&str>, Vec<u8>)> {
+    let (protocol, hex) = match line.split_once(':') {
+        Some((record, hex)) => {
+            let fields: Vec<&str> = record.split_whitespace().collect();
+            if fields.len() != RECORD_FIELDS {
+                return None;
+            }
+            (Some(fields[RECORD_PROTOCOL_FIELD]), hex)
+        }
+        None => (None, line),
+    };
+    // Bytes are two digits each, so a token with an odd count is malformed
+    let tokens: Vec<&str> = hex
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .map(|token| token.trim_start_matches("0x"))
+        .collect();
+    if tokens.is_empty() || tokens.iter().any(|token| token.len() % 2 != 0) {
+        return None;
+    }
+    let data = tokens
+        .iter()
+        .flat_map(|token| token.as_bytes().chunks(2))
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((protocol, data))
+}
+
+/// EM422EM captures, and hex dumps of unknown origin, go through
+/// `decode_power`. Captures logged from other protocols use their decoder.
+fn decode_bytes(source: &str, protocol: Option<&str>, data: &[u8]) {
+    let name = protocol.unwrap_or(Em422em.name());
+    if name == Em422em.name() {
+        return match decode::decode_power(data, data.len()) {
+            Ok(result) => print_result(source, &result),
+            Err(error) => print_error(source, name, &error),
+        };
+    }
+    let Some(protocol) = PROTOCOLS.iter().find(|protocol| protocol.name() == name) else {
+        return print_failure(source, Some(name), "unknown_protocol");
+    };
+    protocol.decode_all(data, &mut |res| match res {
+        Ok(reading) => print_reading(source, &reading),
+        Err(error) => print_error(source, name, &error),
+    });
+}
+
+fn decode_sub(path: &str, text: &str) {
+    let mut pulses: Vec<Pulse> = Vec::new();
+    let mut parser = SubParser::new();
+    parser.feed(text.as_bytes(), &mut |pulse| pulses.push(pulse));
+    let header = parser.finish(&mut |pulse| pulses.push(pulse));
+    if !header.raw {
+        return print_failure(path, None, "not_raw");
+    }
+    if header.is_am() {
+        return print_failure(path, None, "am_preset");
+    }
+    Em422em.decode_pulses(&pulses, &mut |res| match res {
+        Ok(reading) => print_reading(path, &reading),
+        Err(error) => print_error(path, Em422em.name(), &error),
+    });
+}
+
+fn print_result(source: &str, result: &DecodeResult) {
+    let mut json = json_start(source, Some(Em422em.name()));
+    let _ = write!(
+        json,
+        ",\"sensor_id\":\"{:06X}\",\"power_w\":{:.1},\"packet\":\"{}\",\"quality\":{},\
+         \"packet_quality\":{},\"corrected_bits\":{},\"symbol_period\":{}}}",
+        result.fields().transmitter_id,
+        result.power_kw * 1000.0,
+        hex(&result.packet),
+        result.quality_metric,
+        result.packet_quality(),
+        result.corrected_bits,
+        result.symbol_period.samples()
+    );
+    println!("{json}");
+}
+
+fn print_reading(source: &str, reading: &Reading) {
+    let mut json = json_start(source, Some(reading.protocol));
+    let _ = write!(
+        json,
+        ",\"sensor_id\":\"{:06X}\",\"power_w\":{:.1},\"quality\":{},\"corrected_bits\":{}",
+        reading.sensor_id,
+        reading.power_kw * 1000.0,
+        reading.quality,
+        reading.corrected_bits
+    );
+    if let Some(baud_rate) = reading.baud_rate {
+        let _ = write!(json, ",\"baud_rate\":{baud_rate:.0}");
+    }
+    if let Some(energy_kwh) = reading.energy_kwh {
+        let _ = write!(json, ",\"energy_kwh\":{energy_kwh}");
+    }
+    println!("{json}}}");
+}
+
+fn print_error(source: &str, protocol: &str, error: &DecodeError) {
+    let mut json = json_start(source, Some(protocol));
+    let _ = write!(json, ",\"error\":\"{}\"", error.name());
+    match error {
+        DecodeError::ChecksumMismatch {
+            expected,
+            actual,
+            packet,
+            confidence,
+            ..
+        } => {
+            let [a, b, c] = confidence.least_certain::<3>();
+            let _ = write!(
+                json,
+                ",\"expected\":{expected},\"actual\":{actual},\"packet\":\"{}\",\
+                 \"least_certain_bits\":[{a},{b},{c}]",
+                hex(packet)
+            );
+        }
+        DecodeError::IntegrityMismatch {
+            expected,
+            actual,
+            packet,
+        } => {
+            let _ = write!(
+                json,
+                ",\"expected\":{expected},\"actual\":{actual},\"packet\":\"{}\"",
+                hex(packet)
+            );
+        }
+        _ => {}
+    }
+    println!("{json}}}");
+}
+
+/// A failure before any decoder ran.
+fn print_failure(source: &str, protocol: Option<&str>, error: &str) {
+    println!("{},\"error\":\"{error}\"}}", json_start(source, protocol));
+}
+
+/// Opens a JSON object with the fields every line carries.
+fn json_start(source: &str, protocol: Option<&str>) -> String {
+    let mut json = format!("{{\"source\":{}", json_string(source));
+    if let Some(protocol) = protocol {
+        let _ = write!(json, ",\"protocol\":{}", json_string(protocol));
+    }
+    json
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_capture_lines() {
+        assert_eq!(
+            parse_line("81250 433535649 -71 12 -3 Efergy sync_not_found: 33 CC 0F"),
+            Some((Some("Efergy"), vec![0x33, 0xCC, 0x0F]))
+        );
+        assert_eq!(parse_line("33cc0f"), Some((None, vec![0x33, 0xCC, 0x0F])));
+        assert_eq!(parse_line("0x33, 0xCC"), Some((None, vec![0x33, 0xCC])));
+        assert_eq!(parse_line("3 3C"), None);
+        assert_eq!(parse_line("note: 33"), None);
+    }
+
+    #[test]
+    fn escapes_sources() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
@@ -0,0 +1,243 @@
+use crate::decode::{self, DecodeError, Polarity};
+use crate::protocol::{Framing, PowerProtocol, RadioProfile, Reading};
+
+/// Frame bytes after Manchester decoding, not counting the CRC.
+const FRAME_BYTES: usize = 8;
+/// Big endian CRC-16 after the frame, polynomial 0x8005 from zero.
+const CRC_BYTES: usize = 2;
+const CRC_POLY: u16 = 0x8005;
+/// Manchester chips the packet engine captures for a frame and its CRC, two
+/// per bit.
+const FRAME_CHIP_BYTES: usize = (FRAME_BYTES + CRC_BYTES) * 2;
+/// Status bytes the packet engine appends: RSSI, then LQI under the CRC_OK
+/// bit, which stays clear since the engine's CRC is off.
+const STATUS_BYTES: usize = 2;
+const LQI_MASK: u8 = 0x7F;
+/// Channel words have this bit set when the channel is fitted.
+const CHANNEL_VALID: u16 = 0x8000;
+const POWER_MESSAGE: u8 = 0;
+
+/// A frame from a CurrentCost TX clamp or IAM, laid out as rtl_433 decodes
+/// EnviR transmitters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentCostPacket {
+    /// Top nibble of byte 0: 0 for power clamps and IAMs, 4 for impulse
+    /// counters such as optical meter readers.
+    pub sensor_type: u8,
+    /// Transmitter ID from the rest of bytes 0-1.
+    pub device_id: u16,
+    /// Watts on each of up to three clamps, from bytes 2-7 of power messages.
+    pub channels: [Option<u16>; 3],
+}
+
+impl CurrentCostPacket {
+    pub fn from_bytes(frame: &[u8; FRAME_BYTES]) -> Self {
+        let mut channels = [None; 3];
+        if frame[0] >> 4 == POWER_MESSAGE {
+            for (channel, word) in channels.iter_mut().zip(frame[2..].chunks(2)) {
+                let word = u16::from_be_bytes([word[0], word[1]]);
+                if word & CHANNEL_VALID != 0 {
+                    *channel = Some(word & !CHANNEL_VALID);
+                }
+            }
+        }
+        Self {
+            sensor_type: frame[0] >> 4,
+            device_id: u16::from_be_bytes([frame[0] & 0x0F, frame[1]]),
+            channels,
+        }
+    }
+
+    /// Each clamp is its own sensor, numbered 1-3 below the transmitter ID.
+    pub fn sensor_id(&self, channel: usize) -> u32 {
+        (self.device_id as u32) << 4 | (channel as u32 + 1)
+    }
+}
+
+/// CRC-16 as rtl_433 checks CurrentCost frames, MSB first with no final XOR.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ CRC_POLY
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+pub struct CurrentCost;
+
+impl CurrentCost {
+    /// Manchester chips arrive at 4 kbaud after a 16 bit sync word, which the
+    /// packet engine matches before capturing a fixed length frame. The sync
+    /// word isn't valid Manchester, so the radio can't decode the chips, and
+    /// its CRC-16 starts from 0xFFFF where CurrentCost's starts from zero. So
+    /// PKTCTRL.crc_en stays off and the CRC is checked here.
+    const PROFILE: RadioProfile = RadioProfile {
+        frequency_hz: 433_920_000,
+        data_rate_baud: 4_000.0,
+        deviation_hz: 20_000.0,
+        framing: Framing::Packet {
+            sync_word: 0x915D,
+            length: FRAME_CHIP_BYTES as u8,
+        },
+    };
+
+    /// Manchester decodes the frame and checks its CRC.
+    fn frame(data: &[u8]) -> Result<(CurrentCostPacket, u8), DecodeError> {
+        if data.len() < FRAME_CHIP_BYTES + STATUS_BYTES {
+            return Err(DecodeError::NotEnoughData);
+        }
+
+        let mut decoded = [0u8; FRAME_BYTES + CRC_BYTES];
+        let bits =
+            decode::manchester_decode(&data[..FRAME_CHIP_BYTES], 0, Polarity::Normal, &mut decoded);
+        if bits < decoded.len() * 8 {
+            return Err(DecodeError::InsufficientSymbols);
+        }
+
+        let mut frame = [0u8; FRAME_BYTES];
+        frame.copy_from_slice(&decoded[..FRAME_BYTES]);
+        let expected = crc16(&frame);
+        let actual = u16::from_be_bytes([decoded[FRAME_BYTES], decoded[FRAME_BYTES + 1]]);
+        if expected != actual {
+            return Err(DecodeError::IntegrityMismatch {
+                expected,
+                actual,
+                packet: frame,
+            });
+        }
+        // LQI counts down as the link improves
+        let lqi = data[FRAME_CHIP_BYTES + 1];
+        let quality = u8::MAX - (lqi & LQI_MASK) * 2;
+        Ok((CurrentCostPacket::from_bytes(&frame), quality))
+    }
+
+    fn reading(packet: &CurrentCostPacket, channel: usize, watts: u16, quality: u8) -> Reading {
+        Reading {
+            protocol: Self.name(),
+            sensor_id: packet.sensor_id(channel),
+            power_kw: watts as f32 / 1000.0,
+            quality,
+            corrected_bits: 0,
+            baud_rate: None,
+            energy_kwh: None,
+        }
+    }
+}
+
+impl PowerProtocol for CurrentCost {
+    fn name(&self) -> &'static str {
+        "CurrentCost"
+    }
+
+    fn radio_profile(&self) -> RadioProfile {
+        Self::PROFILE
+    }
+
+    /// Reports the first fitted channel. Frames without one, such as impulse
+    /// counts, carry no power reading.
+    fn decode(&self, data: &[u8]) -> Result<Reading, DecodeError> {
+        let (packet, quality) = Self::frame(data)?;
+        packet
+            .channels
+            .iter()
+            .enumerate()
+            .find_map(|(channel, watts)| {
+                watts.map(|watts| Self::reading(&packet, channel, watts, quality))
+            })
+            .ok_or(DecodeError::InsufficientSymbols)
+    }
+
+    fn decode_all(&self, data: &[u8], on_result: &mut dyn FnMut(Result<Reading, DecodeError>)) {
+        let (packet, quality) = match Self::frame(data) {
+            Ok(frame) => frame,
+            Err(err) => return on_result(Err(err)),
+        };
+        for (channel, watts) in packet.channels.iter().enumerate() {
+            if let Some(watts) = *watts {
+                on_result(Ok(Self::reading(&packet, channel, watts, quality)));
+            }
+        }
+    }
+
+    fn claimed_sensor_id(&self, error: &DecodeError) -> Option<u32> {
+        match error {
+            DecodeError::IntegrityMismatch { packet, .. } => {
+                Some(CurrentCostPacket::from_bytes(packet).sensor_id(0))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{manchester, msb_first, pack};
+
+    /// Lays out what the packet engine puts in the RX FIFO for `frame` and its
+    /// CRC, with `lqi` in the status bytes.
+    fn capture(frame: &[u8; FRAME_BYTES], lqi: u8) -> std::vec::Vec<u8> {
+        let crc = crc16(frame).to_be_bytes();
+        let mut fifo = pack(&manchester(msb_first(frame).chain(msb_first(&crc))), false);
+        fifo.extend_from_slice(&[0xE0, lqi]);
+        fifo
+    }
+
+    #[test]
+    fn decodes_each_fitted_channel() {
+        // Transmitter 0x5A3, 1200 W on clamp 1 and 340 W on clamp 3
+        let frame = [0x05, 0xA3, 0x84, 0xB0, 0x00, 0x00, 0x81, 0x54];
+        let mut readings = std::vec::Vec::new();
+        CurrentCost.decode_all(&capture(&frame, 10), &mut |result| {
+            readings.push(result.unwrap())
+        });
+
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0].sensor_id, 0x5A31);
+        assert!((readings[0].power_kw - 1.2).abs() < 1e-6);
+        assert_eq!(readings[1].sensor_id, 0x5A33);
+        assert!((readings[1].power_kw - 0.34).abs() < 1e-6);
+        assert_eq!(readings[0].quality, 235);
+    }
+
+    #[test]
+    fn reads_sensor_type() {
+        let counter = [0x45, 0xA3, 0x02, 0x00, 0x00, 0x01, 0x23, 0x45];
+        let packet = CurrentCostPacket::from_bytes(&counter);
+        assert_eq!(packet.sensor_type, 4);
+        assert_eq!(packet.channels, [None; 3]);
+        assert_eq!(
+            CurrentCost.decode(&capture(&counter, 0)),
+            Err(DecodeError::InsufficientSymbols)
+        );
+    }
+
+    #[test]
+    fn checks_crc() {
+        // CRC-16/UMTS, the same polynomial from zero, gives 0xFEE8 for this
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+
+        let frame = [0x05, 0xA3, 0x84, 0xB0, 0x00, 0x00, 0x00, 0x00];
+        let mut fifo = capture(&frame, 10);
+        // Swap the chips of the last CRC bit, flipping it
+        fifo[FRAME_CHIP_BYTES - 1] ^= 0x03;
+        let err = CurrentCost.decode(&fifo).unwrap_err();
+        let crc = crc16(&frame);
+        assert!(matches!(
+            err,
+            DecodeError::IntegrityMismatch { expected, actual, .. }
+                if expected == crc && actual == crc ^ 1
+        ));
+        assert_eq!(CurrentCost.claimed_sensor_id(&err), Some(0x5A31));
+        assert_eq!(
+            CurrentCost.decode(&[0u8; 4]),
+            Err(DecodeError::NotEnoughData)
+        );
+    }
+}
@@ -1,10 +1,252 @@
-const SCALE_KW: f32 = 0.4799;
+/// Default kW per unit of normalised reading, measured on our own clamp.
+pub const SCALE_KW: f32 = 0.4799;
+/// Mains voltage the transmitter assumes when it reports power.
+pub const NOMINAL_VOLTAGE_V: f32 = 240.0;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DecodeResult {
     pub power_kw: f32,
     pub packet: [u8; 8],
     pub quality_metric: u8,
+    pub confidence: SymbolConfidence,
+    /// Bits flipped by `repair` to make the checksum match, 0 for a packet
+    /// that arrived intact.
+    pub corrected_bits: u8,
+    pub alignment: Alignment,
+    /// Symbol period tracked over the payload.
+    pub symbol_period: SymbolPeriod,
+}
+
+impl DecodeResult {
+    /// Splits the raw packet into its named fields.
+    pub fn fields(&self) -> Em422Packet {
+        Em422Packet::from_bytes(&self.packet)
+    }
+
+    /// Mean symbol confidence, 0-255. Unlike `quality_metric`, which only counts
+    /// preamble repetitions, this reflects how cleanly the payload demodulated.
+    pub fn packet_quality(&self) -> u8 {
+        self.confidence.mean()
+    }
+}
+
+/// Whether the FSK deviation is received the right way up. The transmitter idles
+/// low between preamble and sync, which is what tells the two apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Polarity {
+    #[default]
+    Normal,
+    Inverted,
+}
+
+/// Where the packet sat in the sample stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Alignment {
+    /// Stream bit offset, modulo 4, at which the preamble's `1100` nibbles
+    /// start once polarity is corrected.
+    pub phase: u8,
+    pub polarity: Polarity,
+}
+
+/// A symbol period in 1/256ths of a sample of the bit stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SymbolPeriod(pub u32);
+
+impl SymbolPeriod {
+    const FRAC_BITS: u32 = 8;
+
+    const fn from_samples(samples: u32) -> Self {
+        Self(samples << Self::FRAC_BITS)
+    }
+
+    pub fn samples(&self) -> f32 {
+        self.0 as f32 / (1 << Self::FRAC_BITS) as f32
+    }
+
+    /// The transmitter's baud rate, given the rate the stream was sampled at.
+    pub fn baud_rate(&self, sample_rate_hz: f32) -> f32 {
+        sample_rate_hz / self.samples()
+    }
+}
+
+/// How certain the demodulator was of each packet bit, from 0 (a coin toss) to
+/// 255, indexed MSB first across the packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolConfidence(pub [u8; PACKET_SYMBOLS]);
+
+impl SymbolConfidence {
+    pub fn mean(&self) -> u8 {
+        let total: usize = self.0.iter().map(|&c| c as usize).sum();
+        (total / PACKET_SYMBOLS) as u8
+    }
+
+    /// Indices of the `K` least certain bits, least certain first.
+    pub fn least_certain<const K: usize>(&self) -> [usize; K] {
+        let mut indices = [0usize; K];
+        let mut taken = [false; PACKET_SYMBOLS];
+        for slot in indices.iter_mut() {
+            let (idx, _) = self
+                .0
+                .iter()
+                .enumerate()
+                .filter(|&(idx, _)| !taken[idx])
+                .min_by_key(|&(_, &confidence)| confidence)
+                .unwrap();
+            taken[idx] = true;
+            *slot = idx;
+        }
+        indices
+    }
+}
+
+/// The fields of an 8-byte EM422EM packet.
+///
+/// Every capture we have from the field starts `09 9B 2E 40`, so the first three
+/// bytes are treated as the transmitter ID and the fourth as a flags byte. Bit 6
+/// of the flags is set in all of them; what the other bits signal (low battery,
+/// pairing) has not been confirmed against a transmitter yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Em422Packet {
+    /// 24-bit transmitter ID from bytes 0-2.
+    pub transmitter_id: u32,
+    /// Status flags from byte 3.
+    pub flags: u8,
+    /// Big endian power mantissa from bytes 4-5.
+    pub mantissa: u16,
+    /// Power exponent from byte 6.
+    pub exponent: u8,
+    /// Sum of bytes 0-6 modulo 256, from byte 7.
+    pub checksum: u8,
+}
+
+impl Em422Packet {
+    pub fn from_bytes(packet: &[u8; 8]) -> Self {
+        Self {
+            transmitter_id: u32::from_be_bytes([0, packet[0], packet[1], packet[2]]),
+            flags: packet[3],
+            mantissa: u16::from_be_bytes([packet[4], packet[5]]),
+            exponent: packet[6],
+            checksum: packet[7],
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        let id = self.transmitter_id.to_be_bytes();
+        let mantissa = self.mantissa.to_be_bytes();
+        [
+            id[1],
+            id[2],
+            id[3],
+            self.flags,
+            mantissa[0],
+            mantissa[1],
+            self.exponent,
+            self.checksum,
+        ]
+    }
+
+    /// Builds the packet a transmitter would send for `power_kw`, choosing the
+    /// smallest exponent that fits. Flags are set to what our clamps send.
+    pub fn for_power(transmitter_id: u32, power_kw: f32) -> Option<Self> {
+        let reading = power_kw / SCALE_KW;
+        if !(0.0..).contains(&reading) || transmitter_id > 0xFF_FFFF {
+            return None;
+        }
+        let exponent = (1u8..32).find(|&exponent| reading < (1u32 << exponent) as f32)?;
+        let mantissa = reading / (1u32 << exponent) as f32 * 65536.0 + 0.5;
+        let mut packet = Self {
+            transmitter_id,
+            flags: 0x40,
+            mantissa: (mantissa as u32).min(0xFFFF) as u16,
+            exponent,
+            checksum: 0,
+        };
+        packet.checksum = packet.expected_checksum();
+        Some(packet)
+    }
+
+    /// The checksum the packet should carry given its other fields.
+    pub fn expected_checksum(&self) -> u8 {
+        sum_checksum(&self.to_bytes())
+    }
+
+    pub fn checksum_ok(&self) -> bool {
+        self.expected_checksum() == self.checksum
+    }
+
+    /// Whether the exponent is one a transmitter sends, 1 to 32. Noise that
+    /// happens to pass the checksum can carry any byte here.
+    pub fn exponent_ok(&self) -> bool {
+        (1..=32).contains(&self.exponent)
+    }
+
+    /// The uncalibrated reading, `mantissa / 2^16 * 2^exponent`.
+    pub fn reading(&self) -> f32 {
+        let mantissa_ratio = self.mantissa as f32 / 65536.0;
+        mantissa_ratio * libm::exp2f(self.exponent as f32)
+    }
+
+    pub fn power_kw(&self, calibration: &Calibration) -> f32 {
+        calibration.power_kw(self.reading())
+    }
+}
+
+/// Converts a packet's reading into power for one clamp.
+///
+/// Clamps differ from unit to unit, and the transmitter assumes
+/// [`NOMINAL_VOLTAGE_V`], so 120V installs and reactive loads need correcting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// kW per unit of reading at the nominal voltage.
+    pub scale_kw: f32,
+    /// Actual mains voltage at the clamp, if it isn't the nominal voltage.
+    pub mains_voltage: Option<f32>,
+    pub power_factor: Option<f32>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Calibration {
+    pub const DEFAULT: Self = Self {
+        scale_kw: SCALE_KW,
+        mains_voltage: None,
+        power_factor: None,
+    };
+
+    pub fn power_kw(&self, reading: f32) -> f32 {
+        let voltage_ratio = self.mains_voltage.unwrap_or(NOMINAL_VOLTAGE_V) / NOMINAL_VOLTAGE_V;
+        self.scale_kw * reading * voltage_ratio * self.power_factor.unwrap_or(1.0)
+    }
+
+    /// Applies this calibration to power computed with [`Calibration::DEFAULT`],
+    /// for readings that only carry nominal power.
+    #[cfg(any(test, target_os = "none"))]
+    pub fn apply(&self, nominal_kw: f32) -> f32 {
+        self.power_kw(nominal_kw / SCALE_KW)
+    }
+
+    /// Rescales so that a load which read `measured_kw` with this calibration
+    /// reads `reference_kw`, e.g. a kettle of known rating. Returns `None`
+    /// unless both powers, and the resulting scale, are finite and positive.
+    #[cfg(any(test, target_os = "none"))]
+    pub fn calibrated_to(&self, measured_kw: f32, reference_kw: f32) -> Option<Self> {
+        if !is_positive(measured_kw) || !is_positive(reference_kw) {
+            return None;
+        }
+        let scale_kw = self.scale_kw * reference_kw / measured_kw;
+        is_positive(scale_kw).then_some(Self { scale_kw, ..*self })
+    }
+}
+
+/// Whether `value` is finite and above zero, as every calibration factor
+/// must be.
+#[cfg(any(test, target_os = "none"))]
+pub fn is_positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,114 +255,632 @@ pub enum DecodeError {
     PreambleNotFound,
     SyncNotFound,
     InsufficientSymbols,
-    ChecksumMismatch { expected: u8, actual: u8 },
+    ChecksumMismatch {
+        expected: u8,
+        actual: u8,
+        packet: [u8; 8],
+        quality_metric: u8,
+        confidence: SymbolConfidence,
+        alignment: Alignment,
+        symbol_period: SymbolPeriod,
+    },
+    /// A frame failed its check value, from a decoder without soft symbol data.
+    IntegrityMismatch {
+        expected: u16,
+        actual: u16,
+        packet: [u8; 8],
+    },
+}
+
+impl DecodeError {
+    /// Short name for logs and machine-readable output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DecodeError::NotEnoughData => "not_enough_data",
+            DecodeError::PreambleNotFound => "preamble_not_found",
+            DecodeError::SyncNotFound => "sync_not_found",
+            DecodeError::InsufficientSymbols => "insufficient_symbols",
+            DecodeError::ChecksumMismatch { .. } => "checksum_mismatch",
+            DecodeError::IntegrityMismatch { .. } => "integrity_mismatch",
+        }
+    }
 }
 
-pub fn decode_power(rx_buf: &[u8; 128], read_bytes: usize) -> Result<DecodeResult, DecodeError> {
+const PREAMBLE_PATTERN: u32 = 0xCCCC_CCCC;
+/// Cycles of `1100` a transmitter sends before the gap and sync word.
+const PREAMBLE_CYCLES: usize = 16;
+/// Preamble samples the stream decoder needs to lock, one full window.
+#[cfg(any(test, target_os = "none"))]
+const PREAMBLE_LOCK_SAMPLES: usize = u32::BITS as usize;
+const PREAMBLE_MIN_QUALITY: u8 = 8;
+/// Bits of the 32 bit preamble window allowed to disagree with the pattern.
+const PREAMBLE_MAX_ERRORS: u32 = 2;
+/// Longest run still counted as preamble, which nominally has runs of 2.
+const PREAMBLE_MAX_RUN: usize = 3;
+/// Preamble cycle length assumed when too few edges were seen to measure it.
+const PREAMBLE_PERIOD: SymbolPeriod = SymbolPeriod::from_samples(4);
+/// A payload symbol lasts 8/5 of a preamble cycle, 6.4 samples nominally.
+const SYMBOL_PERIOD_NUM: u32 = 8;
+const SYMBOL_PERIOD_DEN: u32 = 5;
+/// The tracked period moves 1/8 of the way to each measured symbol period.
+const PLL_GAIN_SHIFT: u32 = 3;
+/// Minimum run length of both the idle gap after the preamble and the sync word.
+const SYNC_BITS: usize = 16;
+const PACKET_SYMBOLS: usize = 64;
+
+/// Give up looking for the sync word this many bits after the preamble ends.
+const MAX_SYNC_SEARCH_BITS: usize = 512;
+/// Runs longer than this can't be part of a symbol in [`demodulate_runs`].
+const MAX_RUN_BITS: usize = 32;
+/// Runs longer than this many symbol periods mean the clock has been lost and
+/// the packet is abandoned.
+const LOCK_LOST_PERIODS: u32 = 3;
+
+/// Decodes a whole capture at once, for when the bytes are already buffered.
+#[cfg(not(target_os = "none"))]
+pub fn decode_power(rx_buf: &[u8], read_bytes: usize) -> Result<DecodeResult, DecodeError> {
     if read_bytes == 0 || read_bytes > rx_buf.len() {
         return Err(DecodeError::NotEnoughData);
     }
-    let bit_len = read_bytes * 8;
+
     let buf = &rx_buf[..read_bytes];
 
-    let (preamble_end, quality) =
-        find_preamble(buf, bit_len).ok_or(DecodeError::PreambleNotFound)?;
+    let mut decoder = StreamDecoder::new();
+    for bit_idx in 0..read_bytes * 8 {
+        if let Some(result) = decoder.push_bit(bit_at(buf, bit_idx)) {
+            return result;
+        }
+    }
+    decoder.finish()
+}
 
-    let sync_end = find_sync(buf, preamble_end, bit_len).ok_or(DecodeError::SyncNotFound)?;
+/// How far through a packet the decoder has progressed, used to pick the error
+/// reported when the stream ends without a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Preamble,
+    Sync,
+    Symbols,
+}
 
-    let (packet, _) =
-        demodulate_symbols(buf, sync_end, bit_len).ok_or(DecodeError::InsufficientSymbols)?;
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Correlating against 8 repetitions of `1100` at every bit phase.
+    Preamble,
+    /// Preamble found, following its runs to measure the cycle length.
+    PreambleRun {
+        level: u8,
+        run: usize,
+        timing: PreambleTiming,
+        phase: u8,
+        quality: u8,
+    },
+    /// Looking for the idle gap after the preamble, whose level gives the
+    /// polarity, then a run of at least 16 of the opposite level.
+    Sync {
+        level: u8,
+        run: usize,
+        gap: Option<u8>,
+        bits: usize,
+        phase: u8,
+        quality: u8,
+        preamble_period: SymbolPeriod,
+    },
+    /// Collecting zero/one run pairs into packet bits.
+    Symbols {
+        zeros: usize,
+        ones: usize,
+        builder: PacketBuilder,
+    },
+}
 
-    let expected_checksum = sum_checksum(&packet);
-    if expected_checksum != packet[7] {
-        return Err(DecodeError::ChecksumMismatch {
-            expected: expected_checksum,
-            actual: packet[7],
-        });
+/// Edges seen while following the preamble, measured in samples since it was
+/// found.
+#[derive(Debug, Clone, Copy, Default)]
+struct PreambleTiming {
+    samples: u32,
+    first_edge: u32,
+    last_edge: u32,
+    edges: u32,
+}
+
+impl PreambleTiming {
+    fn rising_edge(&mut self) {
+        if self.edges == 0 {
+            self.first_edge = self.samples;
+        }
+        self.last_edge = self.samples;
+        self.edges += 1;
     }
 
-    let power = decode_word_from_packet(&packet);
-    Ok(DecodeResult {
-        power_kw: power,
-        packet,
-        quality_metric: quality,
-    })
+    /// Mean cycle length between the first and last edge.
+    fn period(&self) -> SymbolPeriod {
+        if self.edges < 2 {
+            return PREAMBLE_PERIOD;
+        }
+        let span = SymbolPeriod::from_samples(self.last_edge - self.first_edge);
+        SymbolPeriod(span.0 / (self.edges - 1))
+    }
+}
+
+/// The symbols demodulated so far for the packet being received.
+#[derive(Debug, Clone, Copy)]
+struct PacketBuilder {
+    packet: [u8; 8],
+    confidence: [u8; PACKET_SYMBOLS],
+    count: usize,
+    /// Symbol period tracked by a first order PLL, seeded from the preamble.
+    period: SymbolPeriod,
+    quality: u8,
+    alignment: Alignment,
 }
 
-fn find_preamble(buf: &[u8], bit_len: usize) -> Option<(usize, u8)> {
-    let mut start = 0usize;
-    while start + 32 <= bit_len {
-        let mut idx = start;
-        let mut quality: u8 = 0;
-        while idx + 4 <= bit_len && matches_1100(buf, idx) {
-            quality = quality.saturating_add(1);
-            idx += 4;
+impl PacketBuilder {
+    fn new(quality: u8, alignment: Alignment, preamble_period: SymbolPeriod) -> Self {
+        Self {
+            packet: [0u8; 8],
+            confidence: [0u8; PACKET_SYMBOLS],
+            count: 0,
+            period: SymbolPeriod(preamble_period.0 * SYMBOL_PERIOD_NUM / SYMBOL_PERIOD_DEN),
+            quality,
+            alignment,
         }
-        if quality >= 8 {
-            return Some((idx, quality));
+    }
+
+    fn push_symbol(&mut self, zeros: usize, ones: usize) {
+        let bit = if zeros >= ones { 0 } else { 1 };
+        let byte_idx = self.count / 8;
+        self.packet[byte_idx] = (self.packet[byte_idx] << 1) | bit;
+        self.confidence[self.count] = symbol_confidence(zeros, ones, self.period);
+        self.track(zeros + ones);
+        self.count += 1;
+    }
+
+    /// Nudges the tracked period towards a measured one. Periods more than 50%
+    /// out are glitches rather than drift, so they are ignored.
+    fn track(&mut self, samples: usize) {
+        let measured = SymbolPeriod::from_samples(samples as u32).0 as i32;
+        let error = measured - self.period.0 as i32;
+        if error.unsigned_abs() < self.period.0 / 2 {
+            self.period.0 = (self.period.0 as i32 + (error >> PLL_GAIN_SHIFT)) as u32;
         }
-        start += 1;
     }
-    None
+
+    /// The last symbol's ones run is cut short by the end of the transmission,
+    /// so score it as if the run filled out the tracked symbol period.
+    fn push_final_symbol(&mut self, zeros: usize, ones: usize) {
+        let period = self.period.samples() as usize;
+        self.push_symbol(zeros, ones.max(period.saturating_sub(zeros)));
+    }
+
+    /// Whether a run has gone on too long to belong to a symbol.
+    fn lost_lock(&self, run: usize) -> bool {
+        SymbolPeriod::from_samples(run as u32).0 > self.period.0 * LOCK_LOST_PERIODS
+    }
+
+    fn is_complete(&self) -> bool {
+        self.count == PACKET_SYMBOLS
+    }
 }
 
-fn matches_1100(buf: &[u8], bit_idx: usize) -> bool {
-    bit_at(buf, bit_idx) == 1
-        && bit_at(buf, bit_idx + 1) == 1
-        && bit_at(buf, bit_idx + 2) == 0
-        && bit_at(buf, bit_idx + 3) == 0
+/// Scores a symbol from 0 to 255. A clean symbol has one run clearly longer
+/// than the other and a period close to the one being tracked.
+fn symbol_confidence(zeros: usize, ones: usize, expected: SymbolPeriod) -> u8 {
+    let period = zeros + ones;
+    // A 4:2 split is typical for a clean symbol, so scale that to full marks
+    let margin = zeros.abs_diff(ones) * 255 * 3 / period;
+    let margin = margin.min(255);
+
+    let measured = SymbolPeriod::from_samples(period as u32);
+    let deviation = measured.0.abs_diff(expected.0) as usize * 255 * 2 / expected.0 as usize;
+    let timing = 255 - deviation.min(255);
+
+    (margin * timing / 255) as u8
 }
 
-fn find_sync(buf: &[u8], start_bit: usize, bit_len: usize) -> Option<usize> {
-    if bit_len < 16 || start_bit >= bit_len - 15 {
-        return None;
+/// Incremental EM422EM decoder fed with bytes as they are read from the RX FIFO.
+///
+/// Runs preamble search, sync search and symbol demodulation as a state machine
+/// and emits a result as soon as a full packet has been collected, so captures
+/// don't need to be buffered and back-to-back transmissions decode.
+#[derive(Debug, Clone)]
+pub struct StreamDecoder {
+    state: State,
+    history: u32,
+    /// Bits pushed since the last reset, wrapping. Only used modulo 4.
+    position: u32,
+    furthest: Stage,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    let mut window: u32 = 0;
-    for idx in start_bit..bit_len {
-        window = ((window << 1) | bit_at(buf, idx) as u32) & 0xFFFF;
-        if idx + 1 >= start_bit + 16 && window == 0xFFFF {
-            return Some(idx + 1);
+impl StreamDecoder {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Preamble,
+            history: 0,
+            position: 0,
+            furthest: Stage::Preamble,
         }
     }
-    None
-}
 
-fn demodulate_symbols(
-    buf: &[u8],
-    mut index: usize,
-    bit_len: usize,
-) -> Option<([u8; 8], usize)> {
-    let mut packet = [0u8; 8];
-    let mut bit_count = 0usize;
+    /// Drops any partially decoded packet and starts a fresh preamble search.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
 
-    while index < bit_len && bit_count < 64 {
-        let mut zeros = 0usize;
-        while index < bit_len && bit_at(buf, index) == 0 {
-            zeros += 1;
-            index += 1;
+    /// Feeds one byte (MSB first), returning a result if it completed a packet.
+    pub fn push_byte(&mut self, byte: u8) -> Option<Result<DecodeResult, DecodeError>> {
+        let mut result = None;
+        for bit_in_byte in (0..8).rev() {
+            if let Some(res) = self.push_bit((byte >> bit_in_byte) & 1) {
+                result = Some(res);
+            }
         }
+        result
+    }
 
-        let mut ones = 0usize;
-        while index < bit_len && bit_at(buf, index) == 1 {
-            ones += 1;
-            index += 1;
+    /// Feeds a single demodulated bit, returning a result if it completed a packet.
+    pub fn push_bit(&mut self, bit: u8) -> Option<Result<DecodeResult, DecodeError>> {
+        self.history = (self.history << 1) | bit as u32;
+        self.position = self.position.wrapping_add(1);
+
+        match self.state {
+            State::Preamble => {
+                if let Some(rotation) = correlate_preamble(self.history) {
+                    let level = bit;
+                    let run = if level == 1 {
+                        self.history.trailing_ones()
+                    } else {
+                        self.history.trailing_zeros()
+                    };
+                    self.enter(State::PreambleRun {
+                        level,
+                        run: run as usize,
+                        timing: PreambleTiming::default(),
+                        phase: (self.position.wrapping_add(rotation) % 4) as u8,
+                        quality: PREAMBLE_MIN_QUALITY,
+                    });
+                }
+            }
+            State::PreambleRun {
+                level,
+                mut run,
+                mut timing,
+                phase,
+                mut quality,
+            } => {
+                timing.samples += 1;
+                if bit == level {
+                    run += 1;
+                } else {
+                    run = 1;
+                    if bit == 1 {
+                        timing.rising_edge();
+                        quality = quality.saturating_add(1);
+                    }
+                }
+
+                if run > PREAMBLE_MAX_RUN {
+                    // The preamble has ended, and this run is the start of the
+                    // gap before the sync word
+                    self.state = State::Sync {
+                        level: bit,
+                        run,
+                        gap: None,
+                        bits: run,
+                        phase,
+                        quality,
+                        preamble_period: timing.period(),
+                    };
+                } else {
+                    self.state = State::PreambleRun {
+                        level: bit,
+                        run,
+                        timing,
+                        phase,
+                        quality,
+                    };
+                }
+            }
+            State::Sync {
+                level,
+                run,
+                gap,
+                bits,
+                phase,
+                quality,
+                preamble_period,
+            } => {
+                let run = if bit == level { run + 1 } else { 1 };
+                let bits = bits + 1;
+                let mut gap = gap;
+                match gap {
+                    None if run >= SYNC_BITS => gap = Some(bit),
+                    Some(gap_level) if bit != gap_level && run >= SYNC_BITS => {
+                        let alignment = if gap_level == 0 {
+                            Alignment {
+                                phase,
+                                polarity: Polarity::Normal,
+                            }
+                        } else {
+                            // Inverting shifts the ones of the preamble by two
+                            Alignment {
+                                phase: (phase + 2) % 4,
+                                polarity: Polarity::Inverted,
+                            }
+                        };
+                        self.enter(State::Symbols {
+                            zeros: 0,
+                            ones: 0,
+                            builder: PacketBuilder::new(quality, alignment, preamble_period),
+                        });
+                        return None;
+                    }
+                    _ => {}
+                }
+                if bits > MAX_SYNC_SEARCH_BITS {
+                    self.state = State::Preamble;
+                } else {
+                    self.state = State::Sync {
+                        level: bit,
+                        run,
+                        gap,
+                        bits,
+                        phase,
+                        quality,
+                        preamble_period,
+                    };
+                }
+            }
+            State::Symbols {
+                mut zeros,
+                mut ones,
+                mut builder,
+            } => {
+                let bit = match builder.alignment.polarity {
+                    Polarity::Normal => bit,
+                    Polarity::Inverted => bit ^ 1,
+                };
+                if bit == 0 {
+                    if ones > 0 {
+                        // A zero after the ones run closes the symbol
+                        builder.push_symbol(zeros, ones);
+                        zeros = 0;
+                        ones = 0;
+                    }
+                    zeros += 1;
+                } else if zeros == 0 {
+                    self.state = State::Preamble;
+                    return None;
+                } else {
+                    ones += 1;
+                    // The final symbol is decided as soon as its ones run wins
+                    if builder.count == PACKET_SYMBOLS - 1 && ones > zeros {
+                        builder.push_final_symbol(zeros, ones);
+                    }
+                }
+
+                if builder.is_complete() {
+                    return Some(self.emit(&builder));
+                }
+                if builder.lost_lock(zeros) || builder.lost_lock(ones) {
+                    self.state = State::Preamble;
+                } else {
+                    self.state = State::Symbols {
+                        zeros,
+                        ones,
+                        builder,
+                    };
+                }
+            }
         }
+        None
+    }
 
-        if zeros == 0 || ones == 0 {
-            break;
+    /// Signals the end of the stream, flushing a packet whose last symbol was
+    /// cut short or reporting how far the decoder got.
+    pub fn finish(&mut self) -> Result<DecodeResult, DecodeError> {
+        if let State::Symbols {
+            zeros,
+            ones,
+            mut builder,
+        } = self.state
+        {
+            if builder.count == PACKET_SYMBOLS - 1 && zeros > 0 && ones > 0 {
+                builder.push_final_symbol(zeros, ones);
+                let result = self.emit(&builder);
+                self.reset();
+                return result;
+            }
         }
 
-        let bit = if zeros >= ones { 0 } else { 1 };
-        let byte_idx = bit_count / 8;
-        packet[byte_idx] = (packet[byte_idx] << 1) | bit;
-        bit_count += 1;
+        let err = match self.furthest {
+            Stage::Preamble => DecodeError::PreambleNotFound,
+            Stage::Sync => DecodeError::SyncNotFound,
+            Stage::Symbols => DecodeError::InsufficientSymbols,
+        };
+        self.reset();
+        Err(err)
+    }
+
+    fn enter(&mut self, state: State) {
+        let stage = match state {
+            State::Preamble => Stage::Preamble,
+            State::PreambleRun { .. } | State::Sync { .. } => Stage::Sync,
+            State::Symbols { .. } => Stage::Symbols,
+        };
+        self.furthest = self.furthest.max(stage);
+        self.state = state;
+    }
+
+    fn emit(&mut self, builder: &PacketBuilder) -> Result<DecodeResult, DecodeError> {
+        self.state = State::Preamble;
+        self.furthest = Stage::Preamble;
+
+        let packet = builder.packet;
+        let confidence = SymbolConfidence(builder.confidence);
+        let fields = Em422Packet::from_bytes(&packet);
+        if !fields.checksum_ok() {
+            return Err(DecodeError::ChecksumMismatch {
+                expected: fields.expected_checksum(),
+                actual: fields.checksum,
+                packet,
+                quality_metric: builder.quality,
+                confidence,
+                alignment: builder.alignment,
+                symbol_period: builder.period,
+            });
+        }
+
+        Ok(DecodeResult {
+            power_kw: fields.power_kw(&Calibration::DEFAULT),
+            packet,
+            quality_metric: builder.quality,
+            confidence,
+            corrected_bits: 0,
+            alignment: builder.alignment,
+            symbol_period: builder.period,
+        })
+    }
+}
+
+/// Samples per symbol in captures built by `encode_packet`.
+const ENCODE_SYMBOL_SAMPLES: usize = 6;
+/// Length of the oversampled capture `encode_packet` writes.
+pub const ENCODED_LEN: usize = 64;
+
+/// Builds the oversampled bit stream a transmitter would produce for `packet`,
+/// as the radio would deliver it. Returns the number of bytes written, or
+/// `None` if `out` is shorter than [`ENCODED_LEN`].
+pub fn encode_packet(packet: &[u8; 8], out: &mut [u8]) -> Option<usize> {
+    let out = out.get_mut(..ENCODED_LEN)?;
+    out.fill(0);
+    let mut idx = 0;
+    let mut push = |bit: u8, count: usize| {
+        for _ in 0..count {
+            out[idx / 8] |= bit << (7 - idx % 8);
+            idx += 1;
+        }
+    };
+
+    for _ in 0..PREAMBLE_CYCLES {
+        push(1, 2);
+        push(0, 2);
     }
+    push(0, 40);
+    push(1, SYNC_BITS);
+    for symbol in 0..PACKET_SYMBOLS {
+        let zeros = match (packet[symbol / 8] >> (7 - symbol % 8)) & 1 {
+            0 => 4,
+            _ => 2,
+        };
+        push(0, zeros);
+        push(1, ENCODE_SYMBOL_SAMPLES - zeros);
+    }
+    Some(ENCODED_LEN)
+}
+
+/// How late a receiver sampling at `sample_rate_hz` can start listening to a
+/// preamble and still lock onto it, in microseconds.
+#[cfg(any(test, target_os = "none"))]
+pub fn preamble_slack_us(sample_rate_hz: f32) -> f32 {
+    (PREAMBLE_CYCLES * 4 - PREAMBLE_LOCK_SAMPLES) as f32 * 1_000_000.0 / sample_rate_hz
+}
 
-    if bit_count < 64 {
+/// Number of least certain bits `repair` chooses from.
+const REPAIR_CANDIDATES: usize = 8;
+const REPAIR_PAIRS: usize = REPAIR_CANDIDATES * (REPAIR_CANDIDATES - 1) / 2;
+
+/// Tries to recover a packet that failed its checksum by flipping one or two of
+/// its least certain bits, most likely candidates first. The checksum is only a
+/// byte wide, so a candidate must also satisfy `plausible`, which should check
+/// it against what the sensor has sent recently.
+pub fn repair(
+    error: &DecodeError,
+    plausible: impl Fn(&Em422Packet) -> bool,
+) -> Option<DecodeResult> {
+    let DecodeError::ChecksumMismatch {
+        packet,
+        quality_metric,
+        confidence,
+        alignment,
+        symbol_period,
+        ..
+    } = *error
+    else {
         return None;
+    };
+
+    let candidates = confidence.least_certain::<REPAIR_CANDIDATES>();
+    let accept = |bits: &[usize]| {
+        let mut repaired = packet;
+        for &bit in bits {
+            repaired[bit / 8] ^= 0x80 >> (bit % 8);
+        }
+        let fields = Em422Packet::from_bytes(&repaired);
+        (fields.checksum_ok() && fields.exponent_ok() && plausible(&fields)).then(|| DecodeResult {
+            power_kw: fields.power_kw(&Calibration::DEFAULT),
+            packet: repaired,
+            quality_metric,
+            confidence,
+            corrected_bits: bits.len() as u8,
+            alignment,
+            symbol_period,
+        })
+    };
+
+    if let Some(result) = candidates.iter().find_map(|&bit| accept(&[bit])) {
+        return Some(result);
+    }
+
+    // Order pairs by their combined confidence so the likeliest goes first
+    let mut pairs = [(0usize, 0usize, 0u16); REPAIR_PAIRS];
+    let mut n = 0;
+    for (i, &a) in candidates.iter().enumerate() {
+        for &b in &candidates[i + 1..] {
+            let score = confidence.0[a] as u16 + confidence.0[b] as u16;
+            pairs[n] = (a, b, score);
+            n += 1;
+        }
     }
-    Some((packet, index))
+    pairs.sort_unstable_by_key(|&(_, _, score)| score);
+    pairs.iter().find_map(|&(a, b, _)| accept(&[a, b]))
+}
+
+/// Compares the last 32 bits against the preamble at each of its four phases,
+/// returning the rotation of the pattern that matches within the error budget.
+/// An inverted preamble is the same pattern two bits along.
+fn correlate_preamble(history: u32) -> Option<u32> {
+    (0..4).find(|&rotation| {
+        (history ^ PREAMBLE_PATTERN.rotate_right(rotation)).count_ones() <= PREAMBLE_MAX_ERRORS
+    })
+}
+
+/// Whether the EM422EM preamble shows up in the `window` bits before the mark
+/// run that ends at `sync_end`, so the sync belongs to an EM422EM frame. Other
+/// protocols sharing the symbol shape use this to leave those frames alone.
+pub fn preamble_before(buf: &[u8], sync_end: usize, polarity: Polarity, window: usize) -> bool {
+    let mark = match polarity {
+        Polarity::Normal => 1,
+        Polarity::Inverted => 0,
+    };
+    let mut sync_start = sync_end.min(buf.len() * 8);
+    while sync_start > 0 && bit_at(buf, sync_start - 1) == mark {
+        sync_start -= 1;
+    }
+    let mut history = 0u32;
+    for (n, bit_idx) in (sync_start.saturating_sub(window)..sync_start).enumerate() {
+        history = history << 1 | bit_at(buf, bit_idx) as u32;
+        if n >= 31 && correlate_preamble(history).is_some() {
+            return true;
+        }
+    }
+    false
 }
 
 fn sum_checksum(packet: &[u8; 8]) -> u8 {
@@ -131,20 +891,97 @@ fn sum_checksum(packet: &[u8; 8]) -> u8 {
     acc
 }
 
-fn decode_word_from_packet(packet: &[u8; 8]) -> f32 {
-    let mantissa = u16::from_be_bytes([packet[4], packet[5]]);
-    let exponent = packet[6] as i32 - 1;
-    let mantissa_ratio = mantissa as f32 / 65536.0;
-    SCALE_KW * mantissa_ratio * ((2 << exponent) as f32)
-}
-
 #[inline(always)]
-fn bit_at(buf: &[u8], bit_idx: usize) -> u8 {
+pub fn bit_at(buf: &[u8], bit_idx: usize) -> u8 {
     let byte_idx = bit_idx / 8;
     let bit_in_byte = 7 - (bit_idx % 8);
     (buf[byte_idx] >> bit_in_byte) & 1
 }
 
+/// Finds the first run of at least `min_len` bits at `level`, starting the
+/// search at `start`, and returns the index of the first bit after the run.
+pub fn find_run(buf: &[u8], start: usize, level: u8, min_len: usize) -> Option<usize> {
+    let mut run = 0;
+    for bit_idx in start..buf.len() * 8 {
+        if bit_at(buf, bit_idx) == level {
+            run += 1;
+        } else if run >= min_len {
+            return Some(bit_idx);
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+/// Batch form of the run-length demodulation in [`StreamDecoder`], for frames
+/// that share the EM422EM symbol shape: each bit is a space run followed by a
+/// mark run, and is a one if the mark run is longer. Fills `out` MSB first
+/// from `start`, returning the index after the last symbol, or `None` if the
+/// stream ends or a run grows too long to be a symbol.
+pub fn demodulate_runs(
+    buf: &[u8],
+    start: usize,
+    polarity: Polarity,
+    out: &mut [u8],
+) -> Option<usize> {
+    let mark = match polarity {
+        Polarity::Normal => 1,
+        Polarity::Inverted => 0,
+    };
+    let mut bit_idx = start;
+    let run = |level: u8, bit_idx: &mut usize| {
+        let mut len = 0;
+        while *bit_idx < buf.len() * 8 && bit_at(buf, *bit_idx) == level && len <= MAX_RUN_BITS {
+            len += 1;
+            *bit_idx += 1;
+        }
+        len
+    };
+
+    let symbols = out.len() * 8;
+    out.fill(0);
+    for symbol in 0..symbols {
+        let spaces = run(mark ^ 1, &mut bit_idx);
+        let marks = run(mark, &mut bit_idx);
+        let last = symbol == symbols - 1;
+        // The final mark run may be cut off by the end of the capture
+        if spaces == 0 || (marks == 0 && !last) || spaces > MAX_RUN_BITS || marks > MAX_RUN_BITS {
+            return None;
+        }
+        if marks > spaces {
+            out[symbol / 8] |= 0x80 >> (symbol % 8);
+        }
+    }
+    Some(bit_idx)
+}
+
+/// Decodes Manchester chips sampled once each, starting at `start`: a mark
+/// then a space is a one and a space then a mark a zero, the other way round
+/// for inverted polarity. Fills `out` MSB first and returns how many bits were
+/// decoded before an invalid chip pair or the end of `buf` or `out`.
+pub fn manchester_decode(buf: &[u8], start: usize, polarity: Polarity, out: &mut [u8]) -> usize {
+    let flip = match polarity {
+        Polarity::Normal => 0,
+        Polarity::Inverted => 1,
+    };
+    out.fill(0);
+    let mut bit_idx = start;
+    let mut decoded = 0;
+    while decoded < out.len() * 8 && bit_idx + 1 < buf.len() * 8 {
+        let first = bit_at(buf, bit_idx);
+        if first == bit_at(buf, bit_idx + 1) {
+            break;
+        }
+        if first ^ flip == 1 {
+            out[decoded / 8] |= 0x80 >> (decoded % 8);
+        }
+        decoded += 1;
+        bit_idx += 2;
+    }
+    decoded
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -209,7 +1046,7 @@ mod tests {
         let mut buf = [0u8; 128];
         buf[..bytes.len()].copy_from_slice(&bytes);
         let err = decode_power(&buf, bytes.len()).expect_err("decoder should fail");
-        assert!(matches!(err, DecodeError::InsufficientSymbols { .. }));
+        assert!(matches!(err, DecodeError::InsufficientSymbols));
     }
 
     #[test]
@@ -222,6 +1059,308 @@ mod tests {
         assert!(matches!(err, DecodeError::ChecksumMismatch { .. }));
     }
 
+    #[test]
+    fn parses_packet_fields() {
+        let hex_str = "33 33 33 33 33 33 33 33 30 00 00 00 00 03 00 00 00 60 00 00 00 00 00 00 00 7F FF 83 0C 18 67 83 0C F1 E0 C3 3C F8 67 DF 06 19 F0 CF 3E F8 60 CF 86 18 30 C3 06 0C F3 CF 9E 79 F0 C7 86 7C F3 C1 86 08 30 61 83 0C 30 67 83 3C 18 67 C3 0C F9 3C F3 FB C4";
+        let bytes = parse_hex(hex_str);
+        let mut buf = [0u8; 128];
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        let result = decode_power(&buf, bytes.len()).expect("decoder should succeed");
+
+        let fields = result.fields();
+        assert_eq!(fields.transmitter_id, 0x099B2E);
+        assert_eq!(fields.flags, 0x40);
+        assert_eq!(fields.mantissa, 0x7EB8);
+        assert_eq!(fields.exponent, 1);
+        assert_eq!(fields.checksum, 0x49);
+        assert!(fields.checksum_ok());
+        assert_eq!(fields.to_bytes(), result.packet);
+        assert_eq!(fields.power_kw(&Calibration::DEFAULT), result.power_kw);
+    }
+
+    #[test]
+    fn applies_calibration() {
+        let fields = Em422Packet::from_bytes(&[9, 155, 46, 64, 64, 0, 2, 84]);
+        assert_eq!(fields.reading(), 1.0);
+        assert_eq!(fields.power_kw(&Calibration::DEFAULT), SCALE_KW);
+
+        let calibration = Calibration {
+            scale_kw: 0.5,
+            mains_voltage: Some(120.0),
+            power_factor: Some(0.9),
+        };
+        assert!((fields.power_kw(&calibration) - 0.225).abs() < 1e-6);
+
+        let recalibrated = calibration.calibrated_to(0.225, 0.45).unwrap();
+        assert!((recalibrated.scale_kw - 1.0).abs() < 1e-6);
+        assert_eq!(recalibrated.mains_voltage, Some(120.0));
+        assert!((fields.power_kw(&recalibrated) - 0.45).abs() < 1e-6);
+
+        assert!(calibration.calibrated_to(0.0, 0.45).is_none());
+        assert!(calibration.calibrated_to(0.225, f32::NAN).is_none());
+        assert!(calibration.calibrated_to(0.225, -0.45).is_none());
+        assert!(calibration.calibrated_to(1e-30, 1e30).is_none());
+    }
+
+    #[test]
+    fn streams_back_to_back_packets() {
+        let first = parse_hex("33 33 33 33 33 33 33 33 30 00 00 00 00 03 00 00 00 60 00 00 00 00 00 00 00 7F FF 83 0C 18 67 83 0C F1 E0 C3 3C F8 67 DF 06 19 F0 CF 3E F8 60 CF 86 18 30 C3 06 0C F3 CF 9E 79 F0 C7 86 7C F3 C1 86 08 30 61 83 0C 30 67 83 3C 18 67 C3 0C F9 3C F3 FB C4");
+        let second = parse_hex("CC CC CC CC CC CC CC 00 00 00 00 00 C0 00 00 18 00 00 00 00 00 00 00 1F FF E0 C3 06 19 E0 C3 3C 78 30 CF BE 19 F3 C1 86 7C 33 CF 9E 18 33 E1 86 0C 30 C1 83 3C 10 61 83 0C 30 60 C3 0C 18 61 C3 06 18 30 C3 06 78 60 CF 86 78 33 C3 86 39 0E DF E5 70 D9 27 E4 A6");
+
+        let mut decoder = StreamDecoder::new();
+        let mut powers = Vec::new();
+        for &byte in first.iter().chain(second.iter()) {
+            if let Some(result) = decoder.push_byte(byte) {
+                powers.push(result.expect("decoder should succeed").power_kw);
+            }
+        }
+
+        assert_eq!(powers.len(), 2);
+        assert!((powers[0] - 0.475096).abs() < 1e-4);
+        assert!((powers[1] - 0.479900).abs() < 1e-4);
+    }
+
+    #[test]
+    fn stream_reports_furthest_stage() {
+        let bytes = parse_hex("33 33 33 33 33 33 33 30 00 00 00 00 03 00 00 00 60 00 00");
+        let mut decoder = StreamDecoder::new();
+        for &byte in &bytes {
+            assert!(decoder.push_byte(byte).is_none());
+        }
+        assert_eq!(decoder.finish(), Err(DecodeError::SyncNotFound));
+        assert_eq!(decoder.finish(), Err(DecodeError::PreambleNotFound));
+    }
+
+    #[test]
+    fn detects_phase_and_polarity() {
+        let vectors: &[(&str, u8)] = &[
+            ("33 33 33 33 33 33 33 33 30 00 00 00 00 03 00 00 00 60 00 00 00 00 00 00 00 7F FF 83 0C 18 67 83 0C F1 E0 C3 3C F8 67 DF 06 19 F0 CF 3E F8 60 CF 86 18 30 C3 06 0C F3 CF 9E 79 F0 C7 86 7C F3 C1 86 08 30 61 83 0C 30 67 83 3C 18 67 C3 0C F9 3C F3 FB C4", 2),
+            ("66 66 66 66 66 66 66 66 00 00 00 00 00 60 00 00 0C 00 00 00 00 00 00 00 0F FF F0 61 83 0C F8 61 9F 3C 18 67 CF 0C F9 E0 C3 3E 19 E7 CF 0C 19 F0 C3 06 18 60 C1 9E 79 F3 CF 06 78 33 CF 9E 18 30 C1 06 0C 30 61 86 0C F0 61 83 0C 30 61 82 18 93 D5 D0 3D 13 0A 6A AA D6 D1 05 FA 6C CE B0 C6 90", 1),
+            ("CC CC CC CC CC CC CC 00 00 00 00 00 C0 00 00 18 00 00 00 00 00 00 00 1F FF E0 C3 06 19 E0 C3 3C 78 30 CF BE 19 F3 C1 86 7C 33 CF 9E 18 33 E1 86 0C 30 C1 83 3C 10 61 83 0C 30 60 C3 0C 18 61 C3 06 18 30 C3 06 78 60 CF 86 78 33 C3 86 39 0E DF E5 70 D9 27 E4 A6", 0),
+        ];
+
+        for &(hex_str, phase) in vectors {
+            let bytes = parse_hex(hex_str);
+            let mut buf = [0u8; 128];
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            let normal = decode_power(&buf, bytes.len()).expect("normal polarity decodes");
+            assert_eq!(normal.alignment.polarity, Polarity::Normal);
+            assert_eq!(normal.alignment.phase, phase);
+
+            for byte in buf.iter_mut() {
+                *byte = !*byte;
+            }
+            let inverted = decode_power(&buf, bytes.len()).expect("inverted polarity decodes");
+            assert_eq!(inverted.alignment.polarity, Polarity::Inverted);
+            assert_eq!(inverted.alignment.phase, phase);
+            assert_eq!(inverted.packet, normal.packet);
+        }
+    }
+
+    #[test]
+    fn measures_symbol_period() {
+        let bytes = parse_hex(
+            "CC CC CC CC CC CC CC 00 00 00 00 00 C0 00 00 18 00 00 00 00 00 00 00 1F FF E0 C3 06 19 E0 C3 3C 78 30 CF BE 19 F3 C1 86 7C 33 CF 9E 18 33 E1 86 0C 30 C1 83 3C 10 61 83 0C 30 60 C3 0C 18 61 C3 06 18 30 C3 06 78 60 CF 86 78 33 C3 86 39 0E DF E5 70 D9 27 E4 A6",
+        );
+        let mut buf = [0u8; 128];
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        let result = decode_power(&buf, bytes.len()).unwrap();
+        assert!((result.symbol_period.samples() - 6.43).abs() < 0.1);
+        assert!((result.symbol_period.baud_rate(32300.0) - 5023.0).abs() < 100.0);
+    }
+
+    #[test]
+    fn tracks_drifting_symbol_clock() {
+        let packet = [0x09, 0x9B, 0x2E, 0x40, 0x96, 0x5A, 0x01, 0x00];
+        let packet = {
+            let mut packet = packet;
+            packet[7] = sum_checksum(&packet);
+            packet
+        };
+
+        // Payload clock slows from 6.4 to 8 samples per symbol
+        let mut bits = Vec::new();
+        for _ in 0..16 {
+            bits.extend_from_slice(&[1, 1, 0, 0]);
+        }
+        bits.extend_from_slice(&[0; 40]);
+        bits.extend_from_slice(&[1; 16]);
+        let mut t = bits.len() as f32;
+        for symbol in 0..PACKET_SYMBOLS {
+            let period = 6.4 + 1.6 * symbol as f32 / PACKET_SYMBOLS as f32;
+            let bit = (packet[symbol / 8] >> (7 - symbol % 8)) & 1;
+            let low = if bit == 0 { 0.625 } else { 0.375 } * period;
+            let (edge, end) = (t + low, t + period);
+            while (bits.len() as f32) + 0.5 < end {
+                bits.push(((bits.len() as f32) + 0.5 >= edge) as u8);
+            }
+            t = end;
+        }
+        bits.extend_from_slice(&[0; 16]);
+
+        let mut decoder = StreamDecoder::new();
+        let result = bits
+            .iter()
+            .find_map(|&bit| decoder.push_bit(bit))
+            .expect("packet decoded")
+            .expect("checksum ok");
+        assert_eq!(result.packet, packet);
+        assert!((result.symbol_period.samples() - 8.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn encoded_packets_decode() {
+        let mut packet = [0x09, 0x9B, 0x2E, 0x40, 0x7F, 0xC3, 0x02, 0x00];
+        packet[7] = sum_checksum(&packet);
+        let mut buf = [0u8; 128];
+        let len = encode_packet(&packet, &mut buf).unwrap();
+        let result = decode_power(&buf, len).unwrap();
+        assert_eq!(result.packet, packet);
+        assert!(encode_packet(&packet, &mut [0u8; 32]).is_none());
+    }
+
+    #[test]
+    fn locks_onto_a_preamble_joined_late() {
+        let mut packet = [0x09, 0x9B, 0x2E, 0x40, 0x7F, 0xC3, 0x02, 0x00];
+        packet[7] = sum_checksum(&packet);
+        let mut buf = [0u8; ENCODED_LEN];
+        encode_packet(&packet, &mut buf).unwrap();
+
+        // The slack is whole bytes, so this keeps exactly the lock window
+        let late = (PREAMBLE_CYCLES * 4 - PREAMBLE_LOCK_SAMPLES) / 8;
+        let result = decode_power(&buf[late..], ENCODED_LEN - late).unwrap();
+        assert_eq!(result.packet, packet);
+        assert!(decode_power(&buf[late + 1..], ENCODED_LEN - late - 1).is_err());
+        assert!((preamble_slack_us(32_300.0) - 990.7).abs() < 0.1);
+    }
+
+    #[test]
+    fn scores_symbol_confidence() {
+        let expected = SymbolPeriod::from_samples(6);
+        // Clean 4:2 split at the expected period
+        assert_eq!(symbol_confidence(4, 2, expected), 255);
+        // Near-even split is barely better than a guess
+        assert!(symbol_confidence(3, 4, expected) < 128);
+        // A clean split at twice the expected period is still suspect
+        assert!(symbol_confidence(8, 4, expected) < 128);
+    }
+
+    #[test]
+    fn reports_packet_confidence() {
+        let bytes = parse_hex(
+            "33 33 33 33 33 33 33 33 30 00 00 00 00 03 00 00 00 60 00 00 00 00 00 00 00 7F FF 83 0C 18 67 83 0C F1 E0 C3 3C F8 67 DF 06 19 F0 CF 3E F8 60 CF 86 18 30 C3 06 0C F3 CF 9E 79 F0 C7 86 7C F3 C1 86 08 30 61 83 0C 30 67 83 3C 18 67 C3 0C F9 3C F3 FB C4",
+        );
+        let mut buf = [0u8; 128];
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        let result = decode_power(&buf, bytes.len()).unwrap();
+        assert!(result.packet_quality() > 128);
+
+        let least = result.confidence.least_certain::<4>();
+        for pair in least.windows(2) {
+            assert!(result.confidence.0[pair[0]] <= result.confidence.0[pair[1]]);
+        }
+        assert_eq!(
+            result.confidence.0[least[0]],
+            *result.confidence.0.iter().min().unwrap()
+        );
+    }
+
+    #[test]
+    fn repairs_least_certain_bits() {
+        let packet = [0x09, 0x9B, 0x2E, 0x40, 0x80, 0x00, 0x01, 0x00];
+        let packet = {
+            let mut packet = packet;
+            packet[7] = sum_checksum(&packet);
+            packet
+        };
+        let mut confidence = [200u8; PACKET_SYMBOLS];
+        confidence[37] = 20;
+        confidence[45] = 30;
+        confidence[3] = 40;
+
+        let corrupt = |bits: &[usize]| {
+            let mut corrupted = packet;
+            for &bit in bits {
+                corrupted[bit / 8] ^= 0x80 >> (bit % 8);
+            }
+            let fields = Em422Packet::from_bytes(&corrupted);
+            DecodeError::ChecksumMismatch {
+                expected: fields.expected_checksum(),
+                actual: fields.checksum,
+                packet: corrupted,
+                quality_metric: 8,
+                confidence: SymbolConfidence(confidence),
+                alignment: Alignment::default(),
+                symbol_period: SymbolPeriod::from_samples(6),
+            }
+        };
+        let known_sensor = |fields: &Em422Packet| fields.transmitter_id == 0x099B2E;
+
+        let single = repair(&corrupt(&[45]), known_sensor).expect("single bit repaired");
+        assert_eq!(single.packet, packet);
+        assert_eq!(single.corrected_bits, 1);
+
+        let double = repair(&corrupt(&[37, 45]), known_sensor).expect("two bits repaired");
+        assert_eq!(double.packet, packet);
+        assert_eq!(double.corrected_bits, 2);
+
+        // Matching checksums are not enough without a plausible reading
+        assert_eq!(repair(&corrupt(&[45]), |_| false), None);
+        // Confident bits are never flipped
+        assert_eq!(repair(&corrupt(&[10]), known_sensor), None);
+        assert_eq!(repair(&DecodeError::SyncNotFound, known_sensor), None);
+    }
+
+    #[test]
+    fn rejects_out_of_range_exponents() {
+        for (exponent, flipped_bit) in [(0x00, 55), (0xFF, 48)] {
+            let mut packet = [0x09, 0x9B, 0x2E, 0x40, 0x80, 0x00, exponent, 0x00];
+            packet[7] = sum_checksum(&packet);
+            let fields = Em422Packet::from_bytes(&packet);
+            assert!(fields.checksum_ok());
+            assert!(!fields.exponent_ok());
+            // Readings are still defined, so decoding such a packet can't panic
+            assert!(fields.reading() >= 0.0);
+
+            // A flip that lands on such a packet is not a repair
+            let mut corrupted = packet;
+            corrupted[flipped_bit / 8] ^= 0x80 >> (flipped_bit % 8);
+            let corrupted_fields = Em422Packet::from_bytes(&corrupted);
+            let mut confidence = [200u8; PACKET_SYMBOLS];
+            confidence[flipped_bit] = 20;
+            let error = DecodeError::ChecksumMismatch {
+                expected: corrupted_fields.expected_checksum(),
+                actual: corrupted_fields.checksum,
+                packet: corrupted,
+                quality_metric: 8,
+                confidence: SymbolConfidence(confidence),
+                alignment: Alignment::default(),
+                symbol_period: SymbolPeriod::from_samples(6),
+            };
+            assert_eq!(repair(&error, |_| true), None);
+        }
+        assert_eq!(
+            Em422Packet::from_bytes(&[0, 0, 0, 0, 0x80, 0, 0, 0]).reading(),
+            0.5
+        );
+    }
+
+    #[test]
+    fn decodes_manchester_chips() {
+        // 1, 0, 1, 1 as 10 01 10 10, then an invalid 11 pair
+        let chips = [0b1001_1010, 0b1100_0000];
+        let mut out = [0u8; 1];
+        assert_eq!(manchester_decode(&chips, 0, Polarity::Normal, &mut out), 4);
+        assert_eq!(out[0], 0b1011_0000);
+        assert_eq!(
+            manchester_decode(&chips, 0, Polarity::Inverted, &mut out),
+            4
+        );
+        assert_eq!(out[0], 0b0100_0000);
+        // Starting a chip late pairs 00 straight away
+        assert_eq!(manchester_decode(&chips, 1, Polarity::Normal, &mut out), 0);
+    }
+
     fn parse_hex(input: &str) -> Vec<u8> {
         input
             .split_whitespace()
@@ -0,0 +1,285 @@
+use crate::decode::{self, DecodeError, Polarity, NOMINAL_VOLTAGE_V};
+use crate::protocol::{Framing, PowerProtocol, RadioProfile, Reading};
+
+/// Shortest mark run accepted as the ~500 us sync pulse.
+const SYNC_MIN_BITS: usize = 12;
+/// More all-zero bytes than this is idle noise rather than a frame.
+const MAX_ZERO_BYTES: usize = 5;
+/// Bits before a sync pulse searched for the EM422EM preamble. Captures of that
+/// frame show up to about 130 bits of gap between its preamble and sync, where
+/// ours start from idle.
+const EM422EM_PREAMBLE_WINDOW: usize = 192;
+
+/// A frame from an Efergy Elite or e2 classic transmitter, as documented by
+/// rtl_433. These clamps measure current, so power depends on the mains voltage
+/// at the install, which is set per sensor like the EM422EM's.
+///
+/// The EM422EM's frame has the same layout and symbols, but leads its sync
+/// pulse with a preamble, which is how the two are told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EfergyPacket {
+    /// Transmitter ID from bytes 0-2, the top nibble always zero.
+    pub transmitter_id: u32,
+    /// Learn, battery and interval flags from byte 3.
+    pub flags: u8,
+    /// Big endian current ADC count from bytes 4-5.
+    pub current_adc: u16,
+    /// Signed exponent from byte 6.
+    pub exponent: i8,
+    /// Sum of bytes 0-6 modulo 256, from byte 7.
+    pub checksum: u8,
+}
+
+impl EfergyPacket {
+    pub fn from_bytes(packet: &[u8; 8]) -> Self {
+        Self {
+            transmitter_id: u32::from_be_bytes([0, packet[0], packet[1], packet[2]]),
+            flags: packet[3],
+            current_adc: u16::from_be_bytes([packet[4], packet[5]]),
+            exponent: packet[6] as i8,
+            checksum: packet[7],
+        }
+    }
+
+    pub fn expected_checksum(packet: &[u8; 8]) -> u8 {
+        packet[..7].iter().fold(0u8, |acc, &b| acc.wrapping_add(b))
+    }
+
+    /// Clamp current in amps, `adc / 2^(15 - exponent)`.
+    pub fn current_a(&self) -> f32 {
+        let shift = self.exponent as i32 - 15;
+        let scale = if shift >= 0 {
+            (1u64 << shift.min(40)) as f32
+        } else {
+            1.0 / (1u64 << (-shift).min(40)) as f32
+        };
+        self.current_adc as f32 * scale
+    }
+
+    /// Power at [`NOMINAL_VOLTAGE_V`], which sensor calibration then corrects.
+    pub fn power_kw(&self) -> f32 {
+        self.current_a() * NOMINAL_VOLTAGE_V / 1000.0
+    }
+}
+
+pub struct Efergy;
+
+impl Efergy {
+    /// Pulses are 64 us and 136 us, so sampling at the EM422EM's rate keeps the
+    /// run lengths the shared demodulator expects.
+    const PROFILE: RadioProfile = RadioProfile {
+        frequency_hz: 433_550_000,
+        data_rate_baud: 32_300.0,
+        deviation_hz: 42_000.0,
+        framing: Framing::Raw,
+    };
+
+    /// Demodulates the frame after a sync pulse ending at `start`. Returns
+    /// `None` if it doesn't look like a frame at all.
+    fn frame_at(data: &[u8], start: usize, polarity: Polarity) -> Option<[u8; 8]> {
+        let mut packet = [0u8; 8];
+        decode::demodulate_runs(data, start, polarity, &mut packet)?;
+
+        // Frames start with a zero nibble, so a leading 1111 means pulses and
+        // gaps were swapped
+        if packet[0] & 0xF0 == 0xF0 {
+            for byte in packet.iter_mut() {
+                *byte = !*byte;
+            }
+        }
+        let zero_bytes = packet.iter().filter(|&&byte| byte == 0).count();
+        if packet[0] & 0xF0 != 0 || zero_bytes > MAX_ZERO_BYTES {
+            return None;
+        }
+        Some(packet)
+    }
+}
+
+impl PowerProtocol for Efergy {
+    fn name(&self) -> &'static str {
+        "Efergy"
+    }
+
+    fn radio_profile(&self) -> RadioProfile {
+        Self::PROFILE
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Reading, DecodeError> {
+        if data.is_empty() {
+            return Err(DecodeError::NotEnoughData);
+        }
+
+        let mut furthest = DecodeError::SyncNotFound;
+        for polarity in [Polarity::Normal, Polarity::Inverted] {
+            let mark = match polarity {
+                Polarity::Normal => 1,
+                Polarity::Inverted => 0,
+            };
+            let mut search = 0;
+            while let Some(start) = decode::find_run(data, search, mark, SYNC_MIN_BITS) {
+                search = start;
+                if decode::preamble_before(data, start, polarity, EM422EM_PREAMBLE_WINDOW) {
+                    continue;
+                }
+                let Some(packet) = Self::frame_at(data, start, polarity) else {
+                    // Keep a checksum failure, which got further
+                    if furthest == DecodeError::SyncNotFound {
+                        furthest = DecodeError::InsufficientSymbols;
+                    }
+                    continue;
+                };
+
+                let fields = EfergyPacket::from_bytes(&packet);
+                let expected = EfergyPacket::expected_checksum(&packet);
+                if fields.checksum != expected {
+                    furthest = DecodeError::IntegrityMismatch {
+                        expected: expected.into(),
+                        actual: fields.checksum.into(),
+                        packet,
+                    };
+                    continue;
+                }
+
+                return Ok(Reading {
+                    protocol: self.name(),
+                    sensor_id: fields.transmitter_id,
+                    power_kw: fields.power_kw(),
+                    quality: u8::MAX,
+                    corrected_bits: 0,
+                    baud_rate: None,
+                    energy_kwh: None,
+                });
+            }
+        }
+        Err(furthest)
+    }
+
+    fn claimed_sensor_id(&self, error: &DecodeError) -> Option<u32> {
+        match error {
+            DecodeError::IntegrityMismatch { packet, .. } => {
+                Some(EfergyPacket::from_bytes(packet).transmitter_id)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{pack, pulse_width_bits};
+    use crate::protocol::Em422em;
+    use crate::pulse::{Pulse, Resampler};
+
+    /// Lays out a frame as the transmitter sends it: idle, sync pulse, then a
+    /// 64 us or 136 us pulse per bit at roughly two samples per 64 us.
+    fn synthesize(packet: &[u8; 8], invert: bool) -> std::vec::Vec<u8> {
+        pack(&pulse_width_bits(packet), invert)
+    }
+
+    /// Lays out a frame from rtl_433's e2 classic timings, a 500 us sync pulse
+    /// and then 200 us bits whose pulse is 136 us for a one and 64 us for a
+    /// zero, sampled at the radio's rate so edges drift across samples.
+    fn from_timings(packet: &[u8; 8]) -> std::vec::Vec<u8> {
+        let mut pulses = std::vec![(false, 2000), (true, 500)];
+        for symbol in 0..64 {
+            let pulse_us = if (packet[symbol / 8] >> (7 - symbol % 8)) & 1 == 1 {
+                136
+            } else {
+                64
+            };
+            pulses.push((false, 200 - pulse_us));
+            pulses.push((true, pulse_us));
+        }
+        pulses.push((false, 2000));
+
+        let mut resampler = Resampler::new(1_000_000.0 / Efergy::PROFILE.data_rate_baud);
+        let mut bits = std::vec::Vec::new();
+        for (level, duration_us) in pulses {
+            resampler.push(Pulse { level, duration_us }, |bit| bits.push(bit));
+        }
+        pack(&bits, false)
+    }
+
+    fn packet(id: u32, adc: u16, exponent: i8) -> [u8; 8] {
+        let id = id.to_be_bytes();
+        let adc = adc.to_be_bytes();
+        let mut packet = [id[1], id[2], id[3], 0x40, adc[0], adc[1], exponent as u8, 0];
+        packet[7] = EfergyPacket::expected_checksum(&packet);
+        packet
+    }
+
+    #[test]
+    fn decodes_current_as_power() {
+        // 0x4000 / 2^(15 - 2) = 2 A, 480 W at 240 V
+        let frame = synthesize(&packet(0x0A1234, 0x4000, 2), false);
+        let reading = Efergy.decode(&frame).unwrap();
+        assert_eq!(reading.sensor_id, 0x0A1234);
+        assert!((reading.power_kw - 0.48).abs() < 1e-6);
+
+        let fields = EfergyPacket::from_bytes(&packet(0x0A1234, 0x4000, 17));
+        assert_eq!(fields.current_a(), 0x4000 as f32 * 4.0);
+    }
+
+    #[test]
+    fn decodes_either_polarity() {
+        let frame = synthesize(&packet(0x0A1234, 0x2000, 2), true);
+        let reading = Efergy.decode(&frame).unwrap();
+        assert_eq!(reading.sensor_id, 0x0A1234);
+        assert!((reading.power_kw - 0.24).abs() < 1e-6);
+    }
+
+    #[test]
+    fn decodes_frames_at_transmitter_timings() {
+        for (id, adc, exponent) in [(0x0A1234, 0x4000, 2), (0x03F00D, 0x1F2E, 1)] {
+            let packet = packet(id, adc, exponent);
+            let reading = Efergy.decode(&from_timings(&packet)).unwrap();
+            assert_eq!(reading.sensor_id, id);
+            assert_eq!(
+                reading.power_kw,
+                EfergyPacket::from_bytes(&packet).power_kw()
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_zero_checksum() {
+        // 0A + 12 + 34 + 40 + 40 + 2E + 02 wraps to zero
+        let packet = [0x0A, 0x12, 0x34, 0x40, 0x40, 0x2E, 0x02, 0x00];
+        assert_eq!(EfergyPacket::expected_checksum(&packet), 0);
+        let reading = Efergy.decode(&synthesize(&packet, false)).unwrap();
+        assert_eq!(reading.sensor_id, 0x0A1234);
+    }
+
+    #[test]
+    fn leaves_em422em_frames_alone() {
+        let capture: std::vec::Vec<u8> = "CC CC CC CC CC CC CC 00 00 00 00 00 C0 00 00 18 00 00 00 00 00 00 00 1F FF E0 C3 06 19 E0 C3 3C 78 30 CF BE 19 F3 C1 86 7C 33 CF 9E 18 33 E1 86 0C 30 C1 83 3C 10 61 83 0C 30 60 C3 0C 18 61 C3 06 18 30 C3 06 78 60 CF 86 78 33 C3 86 39 0E DF E5 70 D9 27 E4 A6"
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+            .collect();
+        assert!(Efergy.decode(&capture).is_err());
+
+        let mut encoded = [0u8; decode::ENCODED_LEN];
+        let reading = Reading {
+            protocol: Em422em.name(),
+            sensor_id: 0x099B2E,
+            power_kw: 1.2,
+            quality: 0,
+            corrected_bits: 0,
+            baud_rate: None,
+            energy_kwh: None,
+        };
+        let len = Em422em.encode(&reading, &mut encoded).unwrap();
+        assert!(Efergy.decode(&encoded[..len]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut bad = packet(0x0A1234, 0x4000, 2);
+        bad[7] ^= 0x01;
+        let err = Efergy.decode(&synthesize(&bad, false)).unwrap_err();
+        assert!(matches!(err, DecodeError::IntegrityMismatch { .. }));
+        assert_eq!(Efergy.claimed_sensor_id(&err), Some(0x0A1234));
+        assert_eq!(Efergy.decode(&[0u8; 32]), Err(DecodeError::SyncNotFound));
+    }
+}
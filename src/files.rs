@@ -0,0 +1,115 @@
+use core::ffi::CStr;
+
+use flipperzero::error;
+use flipperzero::io::{Read, Write};
+use flipperzero::storage::{OpenOptions, Storage};
+use flipperzero_sys::storage_simply_mkdir;
+
+/// Directory on the SD card holding the app's settings and captures.
+pub static APP_DATA_DIR: &CStr = c"/ext/apps_data/powermon";
+/// Directory the stock Sub-GHz app browses for recordings.
+pub static SUBGHZ_DIR: &CStr = c"/ext/subghz";
+
+fn ensure_dir(dir: &CStr) {
+    let storage = Storage::open();
+    unsafe {
+        storage_simply_mkdir(storage.as_ptr(), dir.as_ptr());
+    }
+}
+
+fn ensure_app_dir() {
+    ensure_dir(APP_DATA_DIR);
+}
+
+/// Reads as much of a file as fits in `buf`, returning the number of bytes read.
+pub fn read_file(path: &CStr, buf: &mut [u8]) -> Option<usize> {
+    let mut file = OpenOptions::new().read(true).open(path).ok()?;
+    let mut read_bytes = 0;
+    while read_bytes < buf.len() {
+        match file.read(&mut buf[read_bytes..]) {
+            Ok(0) => break,
+            Ok(n) => read_bytes += n,
+            Err(e) => {
+                error!("Failed to read {}: {}", path.to_str().unwrap_or("?"), e);
+                return None;
+            }
+        }
+    }
+    Some(read_bytes)
+}
+
+/// Reads a file `buf.len()` bytes at a time, for files too large to hold
+/// in memory at once.
+pub fn read_chunks(path: &CStr, buf: &mut [u8], mut on_chunk: impl FnMut(&[u8])) -> bool {
+    let Ok(mut file) = OpenOptions::new().read(true).open(path) else {
+        return false;
+    };
+    loop {
+        match file.read(buf) {
+            Ok(0) => return true,
+            Ok(n) => on_chunk(&buf[..n]),
+            Err(e) => {
+                error!("Failed to read {}: {}", path.to_str().unwrap_or("?"), e);
+                return false;
+            }
+        }
+    }
+}
+
+/// Replaces the contents of a file in the app data directory.
+pub fn write_file(path: &CStr, data: &[u8]) -> bool {
+    ensure_app_dir();
+    let options = OpenOptions::new().write(true).create_always(true);
+    write_with(options, path, data)
+}
+
+/// Creates a Sub-GHz recording, writing the pieces `fill` passes along as
+/// they come.
+pub fn write_subghz_file(path: &CStr, fill: impl FnOnce(&mut dyn FnMut(&[u8]))) -> bool {
+    ensure_dir(SUBGHZ_DIR);
+    let options = OpenOptions::new().write(true).create_always(true);
+    write_streamed(options, path, fill)
+}
+
+/// Adds to the end of a file in the app data directory, creating it if need
+/// be, writing the pieces `fill` passes along as they come.
+pub fn append_file(path: &CStr, fill: impl FnOnce(&mut dyn FnMut(&[u8]))) -> bool {
+    ensure_app_dir();
+    let options = OpenOptions::new().write(true).open_append(true);
+    write_streamed(options, path, fill)
+}
+
+/// Stops writing at the first error.
+fn write_streamed(
+    options: OpenOptions,
+    path: &CStr,
+    fill: impl FnOnce(&mut dyn FnMut(&[u8])),
+) -> bool {
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Failed to open {}: {}", path.to_str().unwrap_or("?"), e);
+            return false;
+        }
+    };
+    let mut result = Ok(());
+    fill(&mut |bytes| {
+        if result.is_ok() {
+            result = file.write_all(bytes);
+        }
+    });
+    if let Err(e) = result {
+        error!("Failed to write {}: {}", path.to_str().unwrap_or("?"), e);
+        return false;
+    }
+    true
+}
+
+fn write_with(options: OpenOptions, path: &CStr, data: &[u8]) -> bool {
+    let result = options.open(path).and_then(|mut file| file.write_all(data));
+    if let Err(e) = result {
+        error!("Failed to write {}: {}", path.to_str().unwrap_or("?"), e);
+        return false;
+    }
+    true
+}
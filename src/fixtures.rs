@@ -0,0 +1,55 @@
+//! Captures for the decoder tests, laid out from each protocol's documented
+//! framing rather than recorded off air. Real Efergy and OWL recordings
+//! should replace them as they turn up.
+
+use std::vec::Vec;
+
+/// Samples of an EM422EM-style pulse-width frame: idle, a 16 sample sync
+/// pulse, then six samples per bit with two spaces for a one and four for a
+/// zero, then idle again.
+pub fn pulse_width_bits(packet: &[u8; 8]) -> Vec<u8> {
+    let mut bits = std::vec![0u8; 20];
+    bits.extend([1; 16]);
+    for bit in msb_first(packet) {
+        let spaces = if bit == 1 { 2 } else { 4 };
+        bits.extend(core::iter::repeat_n(0, spaces));
+        bits.extend(core::iter::repeat_n(1, 6 - spaces));
+    }
+    bits.extend([0; 20]);
+    bits
+}
+
+/// Expands bits into Manchester chips, a one as `10`.
+pub fn manchester(bits: impl IntoIterator<Item = u8>) -> Vec<u8> {
+    bits.into_iter().flat_map(|bit| [bit, bit ^ 1]).collect()
+}
+
+pub fn msb_first(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bytes
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |bit| (byte >> bit) & 1))
+}
+
+pub fn lsb_first(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bytes
+        .iter()
+        .flat_map(|&byte| (0..8).map(move |bit| (byte >> bit) & 1))
+}
+
+/// Packs samples MSB first, as the radio delivers them, inverting them all
+/// if `invert`.
+pub fn pack(bits: &[u8], invert: bool) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (i, &bit)| acc | bit << (7 - i));
+            if invert {
+                !byte
+            } else {
+                byte
+            }
+        })
+        .collect()
+}
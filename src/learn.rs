@@ -0,0 +1,116 @@
+use heapless::Vec;
+
+use crate::protocol::{Reading, SensorKey};
+
+/// Number of distinct transmitters remembered while learning.
+pub const MAX_CANDIDATES: usize = 8;
+
+/// A transmitter heard during a learn window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub key: SensorKey,
+    pub packets: u32,
+    rssi_sum_dbm: f32,
+}
+
+impl Candidate {
+    pub fn mean_rssi_dbm(&self) -> f32 {
+        self.rssi_sum_dbm / self.packets as f32
+    }
+}
+
+/// Collects the transmitters heard while listening for a sensor to pair with.
+#[derive(Debug, Clone, Default)]
+pub struct LearnSession {
+    candidates: Vec<Candidate, MAX_CANDIDATES>,
+}
+
+impl LearnSession {
+    pub const fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Adds a valid packet to its transmitter's tally. Once the list is full a
+    /// new transmitter only gets in by replacing a weaker one.
+    pub fn observe(&mut self, reading: &Reading, rssi_dbm: f32) {
+        let key = reading.key();
+        if let Some(candidate) = self.candidates.iter_mut().find(|c| c.key == key) {
+            candidate.packets += 1;
+            candidate.rssi_sum_dbm += rssi_dbm;
+            return;
+        }
+
+        let candidate = Candidate {
+            key,
+            packets: 1,
+            rssi_sum_dbm: rssi_dbm,
+        };
+        if let Err(candidate) = self.candidates.push(candidate) {
+            let weakest = self
+                .candidates
+                .iter_mut()
+                .min_by(|a, b| a.mean_rssi_dbm().total_cmp(&b.mean_rssi_dbm()))
+                .unwrap();
+            if weakest.mean_rssi_dbm() < rssi_dbm {
+                *weakest = candidate;
+            }
+        }
+    }
+
+    /// Candidates ordered strongest first, which is usually the nearest clamp.
+    pub fn ranked(&self) -> Vec<Candidate, MAX_CANDIDATES> {
+        let mut ranked = self.candidates.clone();
+        ranked.sort_unstable_by(|a, b| b.mean_rssi_dbm().total_cmp(&a.mean_rssi_dbm()));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading_for(id: u32) -> Reading {
+        Reading {
+            protocol: "EM422EM",
+            sensor_id: id,
+            power_kw: 0.5,
+            quality: 255,
+            corrected_bits: 0,
+            baud_rate: None,
+            energy_kwh: None,
+        }
+    }
+
+    #[test]
+    fn ranks_candidates_by_mean_rssi() {
+        let mut session = LearnSession::new();
+        session.observe(&reading_for(0x111111), -90.0);
+        session.observe(&reading_for(0x099B2E), -50.0);
+        session.observe(&reading_for(0x099B2E), -70.0);
+        session.observe(&reading_for(0x222222), -65.0);
+
+        let ranked = session.ranked();
+        let ids: std::vec::Vec<u32> = ranked.iter().map(|c| c.key.id).collect();
+        assert_eq!(ids, [0x099B2E, 0x222222, 0x111111]);
+        assert_eq!(ranked[0].packets, 2);
+        assert_eq!(ranked[0].mean_rssi_dbm(), -60.0);
+    }
+
+    #[test]
+    fn stronger_transmitter_replaces_weakest_when_full() {
+        let mut session = LearnSession::new();
+        for id in 0..MAX_CANDIDATES as u32 {
+            session.observe(&reading_for(id), -80.0 - id as f32);
+        }
+        session.observe(&reading_for(0x099B2E), -40.0);
+        session.observe(&reading_for(0xABCDEF), -100.0);
+
+        let ranked = session.ranked();
+        assert_eq!(ranked.len(), MAX_CANDIDATES);
+        assert_eq!(ranked[0].key.id, 0x099B2E);
+        assert!(ranked.iter().all(|c| c.key.id != MAX_CANDIDATES as u32 - 1));
+        assert!(ranked.iter().all(|c| c.key.id != 0xABCDEF));
+    }
+}
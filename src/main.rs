@@ -7,28 +7,90 @@
 // Required for panic handler
 extern crate flipperzero_rt;
 
-use core::{ffi::CStr};
+use core::cell::Cell;
+use core::{ffi::CStr, fmt::Write};
 
-use flipperzero::{debug, error, info, println};
+use flipperzero::dialogs::{DialogMessage, DialogMessageButton, DialogsApp};
+use flipperzero::gui::canvas::{Align, Canvas};
+use flipperzero::gui::Gui;
+use flipperzero::{debug, error, format, info, println};
 use flipperzero_rt::{entry, manifest};
 use flipperzero_sys::{
-    furi_delay_tick, furi_get_tick, furi_hal_gpio_read, furi_hal_spi_bus_handle_subghz,
+    self as sys, furi_delay_tick, furi_get_tick, furi_hal_gpio_read,
+    furi_hal_spi_bus_handle_subghz, gpio_button_back,
 };
+use heapless::String;
 
 use crate::cc1101::{
-    CC1101Device, BS_LIMIT, BS_PRE_KI, BS_PRE_KP, CARRIER_SENSE_ABS_THR, CARRIER_SENSE_REL_THR,
-    CMD, FOC_LIMIT, FOC_PRE_K, FREQSYNTHCAL, GDO_PIN_CONFIG, MAGN_TARGET, MOD_FORMAT, NUM_PREAMBLE,
-    PKTCTRL, PKT_ADDR_CHECK, PKT_FORMAT, PKT_LENGTH_CONFIG, SYNC_MODE,
+    CC1101Device, WorConfig, BS_LIMIT, BS_PRE_KI, BS_PRE_KP, CARRIER_SENSE_ABS_THR,
+    CARRIER_SENSE_REL_THR, CMD, FOC_LIMIT, FOC_PRE_K, FS_AUTOCAL, GDO_PIN_CONFIG, MAGN_TARGET,
+    MOD_FORMAT, NUM_PREAMBLE, PKTCTRL, PKT_ADDR_CHECK, PKT_FORMAT, PKT_LENGTH_CONFIG, SYNC_MODE,
 };
 
+use crate::decode::{preamble_slack_us, DecodeError};
+use crate::learn::LearnSession;
+use crate::protocol::{
+    CaptureStream, Em422em, Framing, PowerProtocol, RadioProfile, Reading, SensorKey, PROTOCOLS,
+    PROTOCOL_COUNT,
+};
+use crate::pulse::{bit_pulses, Pulse, PulseRing};
+use crate::recorder::CaptureRecord;
+use crate::scan::{ChannelActivity, ScanPlan, MAX_SCAN_CHANNELS};
+use crate::schedule::TransmissionSchedule;
+use crate::sensors::SensorRegistry;
+use crate::spectrum::{Spectrum, BAR_HEIGHT, SPECTRUM_WIDTH, WATERFALL_ROWS};
+use crate::subfile::{fsk_preset, write_sub, SubParser};
+
 mod cc1101;
+mod currentcost;
 mod decode;
-mod debug;
+mod efergy;
+mod files;
+#[cfg(test)]
+mod fixtures;
+mod learn;
+mod owl;
+mod protocol;
+mod pulse;
+mod recorder;
+mod scan;
+mod schedule;
+mod sensors;
+mod spectrum;
+mod subfile;
 
-static BAUD_RATE: f32 = 16150.0;
-static FSK_DEV: f32 = 84_000.0;
-static TRANSMIT_FREQ: u32 = 433535649;
-static TRANSMIT_FREQ_MHZ: f32 = (TRANSMIT_FREQ as f32) / 1_000_000f32;
+static LEARN_WINDOW_S: u32 = 30;
+static CALIBRATION_READINGS: u32 = 3;
+static CALIBRATION_ATTEMPTS: u32 = 20;
+/// Bytes of each burst kept for `record` and `save`, enough for a couple of
+/// packets. Decoding sees the whole burst as it arrives.
+const BURST_LEN: usize = 512;
+/// Results held from one burst until the radio is idle to report them.
+const MAX_BURST_RESULTS: usize = 8;
+/// Longest a packet-engine capture may take after its sync word.
+const PACKET_TIMEOUT_TICKS: u32 = 100;
+/// Default scan covers the 433 MHz ISM band.
+const SCAN_START_HZ: u32 = 433_050_000;
+const SCAN_STOP_HZ: u32 = 434_790_000;
+const SCAN_STEP_HZ: u32 = 50_000;
+/// Time spent on each channel. EM422EMs transmit about every 6 s, so a little
+/// longer hears at least one burst from a meter within reach of the channel.
+const SCAN_DWELL_TICKS: u32 = 6500;
+/// Time for RSSI to settle after entering RX on a new channel.
+const SPECTRUM_SETTLE_TICKS: u32 = 1;
+static FSCAL_PATH: &CStr = c"/ext/apps_data/powermon/fscal.txt";
+/// Where `record` appends every capture, one line each.
+static CAPTURE_LOG_PATH: &CStr = c"/ext/apps_data/powermon/captures.txt";
+const FSCAL_FILE_LEN: usize = 512;
+/// Longest RX window of each `lowpower` wake that finds no carrier. The period
+/// comes from how much of the EM422EM preamble the decoder can miss.
+const WOR_DUTY_CYCLE: f32 = 0.125;
+/// Time to listen for each asynchronous serial capture.
+const ASYNC_CAPTURE_TICKS: u32 = 6000;
+/// How often `lowpower` checks the Back button between listens.
+const BACK_POLL_TICKS: u32 = 2000;
+/// Bytes read at a time when importing a Sub-GHz recording.
+const IMPORT_CHUNK_LEN: usize = 256;
 
 // Define the FAP Manifest for this application
 manifest!(
@@ -37,16 +99,55 @@ manifest!(
     has_icon = true,
     // See https://github.com/flipperzero-rs/flipperzero/blob/v0.11.0/docs/icons.md for icon format
     icon = "rustacean-10x10.icon",
+    // Listening keeps a decoder for each protocol sharing the channel
+    stack_size = 8 * 1024,
 );
 
 // Define the entry function
 entry!(main);
 
 // Entry point
-fn main(_args: Option<&CStr>) -> i32 {
+fn main(args: Option<&CStr>) -> i32 {
+    let mut sensors = SensorRegistry::new();
+    sensors.load_from_sd();
+
+    // Sensors are managed by launching the app with arguments, e.g.
+    // `name 099B2E Kitchen`, `select 099B2E`, `pair 099B2E`, `learn 30`,
+    // `set 099B2E voltage=120 pf=0.95` or `calibrate 099B2E 2000`. `repair off`
+    // stops flipping uncertain bits in packets that fail their checksum, and
+    // `repair on` resumes it. `selftest` checks each protocol decodes its own
+    // encoder's output, and `scan 433.05 434.79 50` looks for an EM422EM's
+    // carrier. `spectrum` takes the same range and shows it live. `lowpower`
+    // listens until Back is held, through Wake-on-Radio and only around each
+    // sensor's next transmission, for long logging sessions on battery. `async`
+    // times the demodulated signal's edges instead of oversampling it, for
+    // EM422EMs whose baud rate has drifted from the profile's.
+    // `import /ext/subghz/meter.sub` decodes a RAW recording made with the
+    // Sub-GHz app, and `save` listens as usual but also writes each capture
+    // that decodes to /ext/subghz as one. `record` logs every capture, decoded
+    // or not, with the radio's RSSI, LQI and frequency estimate, for adding
+    // failures to the decoder's tests.
+    let args = args.and_then(|args| args.to_str().ok()).unwrap_or("");
+    let mut words = args.split_whitespace();
+    let command = words.next();
+    match command {
+        Some("name") => return name_sensor(&mut sensors, words.next(), words.next()),
+        Some("select") => return select_sensor(&mut sensors, words.next()),
+        Some("pair") => return pair_sensor(&mut sensors, words.next(), true),
+        Some("unpair") => return pair_sensor(&mut sensors, words.next(), false),
+        Some("set") => return configure_sensor(&mut sensors, words.next(), words),
+        Some("repair") => return set_repair(&mut sensors, words.next()),
+        Some("list") => {
+            print_sensors(&sensors);
+            return 0;
+        }
+        Some("selftest") => return self_test(),
+        Some("import") => return import_sub(&sensors, words.next()),
+        _ => {}
+    }
+
     info!("Starting Radio!");
     let mut cc1101_device: CC1101Device;
-    let mut rx_buf = [0u8; 128];
     unsafe {
         cc1101_device = CC1101Device::new(&furi_hal_spi_bus_handle_subghz);
     }
@@ -60,9 +161,6 @@ fn main(_args: Option<&CStr>) -> i32 {
     cc1101_device.fifo_thr.set_fifo_thr(0xF);
     cc1101_device.write_register(cc1101_device.fifo_thr);
 
-    cc1101_device.pktlen.set_packet_length(255);
-    cc1101_device.write_register(cc1101_device.pktlen);
-
     cc1101_device.pktctrl = PKTCTRL::new()
         .with_pqt(0x00)
         .with_append_status(false)
@@ -70,12 +168,9 @@ fn main(_args: Option<&CStr>) -> i32 {
         .with_white_data(false)
         .with_pkt_format(PKT_FORMAT::NORMAL)
         .with_crc_en(false)
-        .with_length_config(PKT_LENGTH_CONFIG::FIXED);
+        .with_length_config(PKT_LENGTH_CONFIG::INFINITE);
     cc1101_device.write_register(cc1101_device.pktctrl);
 
-    cc1101_device.freq_ctrl.set_freq_mhz(TRANSMIT_FREQ_MHZ);
-    cc1101_device.write_register(cc1101_device.freq_ctrl);
-
     cc1101_device.modem_config.set_chanbw_e(3);
     cc1101_device.modem_config.set_chanbw_m(0);
     cc1101_device.modem_config.set_mod_format(MOD_FORMAT::FSK2);
@@ -86,12 +181,8 @@ fn main(_args: Option<&CStr>) -> i32 {
     cc1101_device
         .modem_config
         .set_num_preamble(NUM_PREAMBLE::P4);
-    cc1101_device.modem_config.set_data_rate(BAUD_RATE * 2.0);
     cc1101_device.write_register(cc1101_device.modem_config);
 
-    cc1101_device.deviatn.set_deviation(FSK_DEV / 2.0);
-    cc1101_device.write_register(cc1101_device.deviatn);
-
     cc1101_device.agc_ctrl.set_magn_target(MAGN_TARGET::D33);
     cc1101_device
         .agc_ctrl
@@ -114,91 +205,1281 @@ fn main(_args: Option<&CStr>) -> i32 {
     cc1101_device.bit_sync.set_bs_limit(BS_LIMIT::PM0);
     cc1101_device.write_register(cc1101_device.bit_sync);
 
-    // Set FSCAL from RF Studio
-    cc1101_device.freq_synth_cal = FREQSYNTHCAL::from_bytes([0x1F, 0x00, 0x2A, 0xE9]);
-    cc1101_device.write_register(cc1101_device.freq_synth_cal);
-
     // Set TEST register from RF Studio
     cc1101_device.test_settings.set_test0(0x09);
     cc1101_device.test_settings.set_test1(0x35);
     cc1101_device.test_settings.set_test2(0x81);
     cc1101_device.write_register(cc1101_device.test_settings);
 
+    load_fscal_cache(&mut cc1101_device);
+    configure_radio(&mut cc1101_device, &PROTOCOLS[0].radio_profile());
     cc1101_device.print_state(true);
 
+    let status = match command {
+        Some("learn") => {
+            let window_s = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .unwrap_or(LEARN_WINDOW_S);
+            learn(&mut cc1101_device, &mut sensors, window_s)
+        }
+        Some("calibrate") => {
+            calibrate(&mut cc1101_device, &mut sensors, words.next(), words.next())
+        }
+        Some("scan") => scan(&mut cc1101_device, words.next(), words.next(), words.next()),
+        Some("spectrum") => spectrum(&mut cc1101_device, words.next(), words.next(), words.next()),
+        Some("async") => {
+            configure_async_serial(&mut cc1101_device);
+            let mut ring = PulseRing::new();
+            for _i in 0..10 {
+                let Some((pulses, signal)) =
+                    receive_pulses(&mut cc1101_device, ASYNC_CAPTURE_TICKS, &mut ring)
+                else {
+                    continue;
+                };
+                Em422em.decode_pulses(pulses, &mut |res| {
+                    handle_result(&mut cc1101_device, &mut sensors, &Em422em, &signal, res)
+                });
+            }
+            print_sensors(&sensors);
+            0
+        }
+        Some("lowpower") => match WorConfig::for_preamble(
+            preamble_slack_us(Em422em.radio_profile().data_rate_baud),
+            WOR_DUTY_CYCLE,
+        ) {
+            Some(config) => {
+                info!(
+                    "WOR every {} us, listening {} us, deaf at most {} us",
+                    (config.interval_ms() * 1000.0) as u32,
+                    (config.rx_window_ms() * 1000.0) as u32,
+                    (config.longest_gap_ms() * 1000.0) as u32
+                );
+                cc1101_device.enable_wor(config);
+                let status = listen_until_back(&mut cc1101_device, &mut sensors);
+                cc1101_device.disable_wor();
+                status
+            }
+            None => {
+                error!("Invalid WOR settings");
+                1
+            }
+        },
+        _ => {
+            let save = command == Some("save");
+            let record = command == Some("record");
+            let wanted = wanted_protocols(&sensors);
+            for _i in 0..10 {
+                listen_captures(
+                    &mut cc1101_device,
+                    6000,
+                    &|protocol| wanted.contains(&protocol.name()),
+                    |dev, protocol, signal, res| {
+                        handle_result(dev, &mut sensors, protocol, signal, res)
+                    },
+                    |dev, protocol, signal, capture, outcome| {
+                        if record {
+                            record_capture(dev, protocol, signal, capture, outcome);
+                        }
+                        if save && outcome.is_ok() {
+                            save_capture(protocol, capture);
+                        }
+                    },
+                );
+            }
+            print_sensors(&sensors);
+            println!("Done, Exiting!");
+            0
+        }
+    };
+    save_fscal_cache(&cc1101_device);
+    status
+}
+
+fn load_fscal_cache(cc1101_device: &mut CC1101Device) {
+    let mut buf = [0u8; FSCAL_FILE_LEN];
+    if let Some(read_bytes) = files::read_file(FSCAL_PATH, &mut buf) {
+        let text = core::str::from_utf8(&buf[..read_bytes]).unwrap_or("");
+        cc1101_device.fscal_cache.load(text);
+    }
+}
+
+/// Persists calibrations run this session, so the next launch can skip SCAL.
+fn save_fscal_cache(cc1101_device: &CC1101Device) {
+    if !cc1101_device.fscal_cache.is_dirty() {
+        return;
+    }
+    let mut text: String<FSCAL_FILE_LEN> = String::new();
+    if cc1101_device.fscal_cache.save(&mut text).is_ok() {
+        files::write_file(FSCAL_PATH, text.as_bytes());
+    }
+}
+
+/// Tunes the radio to a protocol's frequency, modem settings and framing.
+/// Returns false if the frequency is outside every band the CC1101 covers.
+fn configure_radio(cc1101_device: &mut CC1101Device, profile: &RadioProfile) -> bool {
+    if cc1101_device.set_frequency(profile.frequency_hz).is_none() {
+        error!("Unsupported frequency {} Hz", profile.frequency_hz);
+        return false;
+    }
+
+    cc1101_device
+        .modem_config
+        .set_data_rate(profile.data_rate_baud);
+
+    cc1101_device.deviatn.set_deviation(profile.deviation_hz);
+    cc1101_device.write_register(cc1101_device.deviatn);
+
+    match profile.framing {
+        Framing::Raw => {
+            cc1101_device
+                .gdo_config
+                .set_gdo0_cfg(GDO_PIN_CONFIG::CarrierSense);
+            cc1101_device
+                .pktctrl
+                .set_length_config(PKT_LENGTH_CONFIG::INFINITE);
+            cc1101_device.pktctrl.set_crc_en(false);
+            cc1101_device.pktctrl.set_append_status(false);
+            cc1101_device
+                .modem_config
+                .set_sync_mode(SYNC_MODE::NO_PREAMBLE_SYNC_CS);
+        }
+        Framing::Packet { sync_word, length } => {
+            let [sync_hi, sync_lo] = sync_word.to_be_bytes();
+            cc1101_device.sync.set_sync_hi(sync_hi);
+            cc1101_device.sync.set_sync_lo(sync_lo);
+            cc1101_device.write_register(cc1101_device.sync);
+            cc1101_device.pktlen.set_packet_length(length);
+            cc1101_device.write_register(cc1101_device.pktlen);
+
+            // GDO0 asserts on the sync word and drops at the end of the packet
+            cc1101_device
+                .gdo_config
+                .set_gdo0_cfg(GDO_PIN_CONFIG::SyncWordSymbol);
+            cc1101_device
+                .pktctrl
+                .set_length_config(PKT_LENGTH_CONFIG::FIXED);
+            // The engine's CRC-16 starts from 0xFFFF and would run over the
+            // Manchester chips, so no profile can use it and decoders check
+            // their own
+            cc1101_device.pktctrl.set_crc_en(false);
+            cc1101_device.pktctrl.set_append_status(true);
+            cc1101_device.modem_config.set_sync_mode(SYNC_MODE::S16_16);
+        }
+    }
+    cc1101_device.write_register(cc1101_device.gdo_config);
+    cc1101_device.write_register(cc1101_device.pktctrl);
+    cc1101_device.write_register(cc1101_device.modem_config);
+    true
+}
+
+/// Switches to asynchronous serial mode, where GDO0 follows the demodulator
+/// directly and the packet engine and FIFO are bypassed.
+fn configure_async_serial(cc1101_device: &mut CC1101Device) {
+    cc1101_device
+        .gdo_config
+        .set_gdo0_cfg(GDO_PIN_CONFIG::SerialDataOutput);
+    cc1101_device.write_register(cc1101_device.gdo_config);
+
+    cc1101_device
+        .pktctrl
+        .set_pkt_format(PKT_FORMAT::ASYNC_SERIAL);
+    cc1101_device
+        .pktctrl
+        .set_length_config(PKT_LENGTH_CONFIG::INFINITE);
+    cc1101_device.pktctrl.set_crc_en(false);
+    cc1101_device.pktctrl.set_append_status(false);
+    cc1101_device.write_register(cc1101_device.pktctrl);
+}
+
+/// What the radio measured of a burst while its carrier was up. Once the radio
+/// idles its status registers describe nothing.
+#[derive(Debug, Clone, Copy)]
+struct Signal {
+    rssi_dbm: f32,
+    lqi: u8,
+    /// FREQEST, in FSCTRL0 steps.
+    freq_est: i8,
+    /// The FSCTRL0 offset FREQEST is relative to.
+    freq_offset: i8,
+}
+
+impl Signal {
+    fn latch(cc1101_device: &mut CC1101Device) -> Self {
+        cc1101_device.sync_field(|dev| &mut dev.rssi);
+        cc1101_device.sync_field(|dev| &mut dev.lqi);
+        Self {
+            rssi_dbm: cc1101_device.rssi.rssi_dbm(),
+            lqi: cc1101_device.lqi.lqi_est(),
+            freq_est: cc1101_device.read_freq_estimate(),
+            freq_offset: cc1101_device.freq_offset(),
+        }
+    }
+}
+
+unsafe extern "C" fn record_pulse(level: bool, duration_us: u32, context: *mut core::ffi::c_void) {
+    let ring = &*(context as *const PulseRing);
+    ring.push(Pulse { level, duration_us });
+}
+
+/// Times GDO0 edges for up to `timeout_ticks`, stopping once carrier sense has
+/// come and gone. Noise keeps GDO0 toggling, so the ring keeps the pulses
+/// leading up to the end of the burst. Returns them with the signal as the
+/// carrier came up, or `None` if no carrier was seen.
+fn receive_pulses<'a>(
+    cc1101_device: &mut CC1101Device,
+    timeout_ticks: u32,
+    ring: &'a mut PulseRing,
+) -> Option<(&'a [Pulse], Signal)> {
+    ring.clear();
+    cc1101_device.start_async_rx(record_pulse, ring as *mut PulseRing as *mut _);
+    let mut signal = None;
+    for _ in 0..timeout_ticks {
+        unsafe { furi_delay_tick(1) };
+        cc1101_device.sync_field(|dev| &mut dev.pkt_status);
+        let carrier = cc1101_device.pkt_status.carrier_sense();
+        if signal.is_some() && !carrier {
+            break;
+        }
+        if carrier && signal.is_none() {
+            signal = Some(Signal::latch(cc1101_device));
+        }
+    }
+    cc1101_device.stop_async_rx();
+    signal.map(|signal| (ring.ordered(), signal))
+}
+
+/// Gives each channel the registered protocols use a turn at the radio, sharing
+/// `timeout_ticks` between them, and reports everything decoded. Returns false
+/// if no carrier was seen at all.
+fn listen(
+    cc1101_device: &mut CC1101Device,
+    timeout_ticks: u32,
+    on_result: impl FnMut(&mut CC1101Device, &dyn PowerProtocol, &Signal, Result<Reading, DecodeError>),
+) -> bool {
+    listen_captures(
+        cc1101_device,
+        timeout_ticks,
+        &|_| true,
+        on_result,
+        |_, _, _, _, _| {},
+    )
+}
+
+/// Names of the protocols worth listening for, given which sensors are paired.
+fn wanted_protocols(sensors: &SensorRegistry) -> heapless::Vec<&'static str, PROTOCOL_COUNT> {
+    PROTOCOLS
+        .iter()
+        .map(|protocol| protocol.name())
+        .filter(|&name| sensors.wants_protocol(name))
+        .collect()
+}
+
+/// The first `wanted` protocol whose profile receives `protocol` too. It tunes
+/// the radio for every protocol it leads.
+fn channel_leader(protocol: usize, wanted: &dyn Fn(&dyn PowerProtocol) -> bool) -> usize {
+    let profile = PROTOCOLS[protocol].radio_profile();
+    (0..PROTOCOL_COUNT)
+        .find(|&leader| {
+            wanted(PROTOCOLS[leader]) && PROTOCOLS[leader].radio_profile().shares_channel(&profile)
+        })
+        .unwrap_or(protocol)
+}
+
+/// One protocol's share of a listen.
+struct Listener {
+    protocol: &'static dyn PowerProtocol,
+    stream: CaptureStream,
+    /// `Ok` once a packet decodes cleanly, or else the first error.
+    outcome: Option<Result<(), DecodeError>>,
+}
+
+type BurstResults =
+    heapless::Vec<(&'static dyn PowerProtocol, Result<Reading, DecodeError>), MAX_BURST_RESULTS>;
+
+/// Like `listen`, but only for protocols that are `wanted`, and also handing
+/// `on_capture` the start of each capture once it's decoded, with `Ok` if any
+/// packet in it decoded cleanly or else the first error. Protocols whose
+/// profiles share a channel listen together rather than taking turns.
+fn listen_captures(
+    cc1101_device: &mut CC1101Device,
+    timeout_ticks: u32,
+    wanted: &dyn Fn(&dyn PowerProtocol) -> bool,
+    mut on_result: impl FnMut(
+        &mut CC1101Device,
+        &dyn PowerProtocol,
+        &Signal,
+        Result<Reading, DecodeError>,
+    ),
+    mut on_capture: impl FnMut(
+        &mut CC1101Device,
+        &dyn PowerProtocol,
+        &Signal,
+        &[u8],
+        Result<(), DecodeError>,
+    ),
+) -> bool {
+    let is_leader = |protocol: usize| {
+        wanted(PROTOCOLS[protocol]) && channel_leader(protocol, wanted) == protocol
+    };
+    let leaders = (0..PROTOCOL_COUNT)
+        .filter(|&protocol| is_leader(protocol))
+        .count();
+    let mut heard = false;
+    let mut burst = [0u8; BURST_LEN];
+    for leader in (0..PROTOCOL_COUNT).filter(|&protocol| is_leader(protocol)) {
+        let profile = PROTOCOLS[leader].radio_profile();
+        if !configure_radio(cc1101_device, &profile) {
+            continue;
+        }
+        let mut listeners: heapless::Vec<Listener, PROTOCOL_COUNT> = (0..PROTOCOL_COUNT)
+            .filter(|&protocol| {
+                wanted(PROTOCOLS[protocol]) && channel_leader(protocol, wanted) == leader
+            })
+            .map(|protocol| Listener {
+                protocol: PROTOCOLS[protocol],
+                stream: CaptureStream::new(),
+                outcome: None,
+            })
+            .collect();
+        let mut results = BurstResults::new();
+        let mut len = 0;
+        let timeout = timeout_ticks / leaders as u32;
+        let mut on_chunk = |chunk: &[u8]| {
+            let keep = chunk.len().min(burst.len() - len);
+            burst[len..len + keep].copy_from_slice(&chunk[..keep]);
+            len += keep;
+            for listener in listeners.iter_mut() {
+                let protocol = listener.protocol;
+                protocol.decode_chunk(&mut listener.stream, chunk, &mut |res| {
+                    queue_result(&mut results, &mut listener.outcome, protocol, res)
+                });
+            }
+        };
+        let received = match profile.framing {
+            Framing::Raw => receive_burst(cc1101_device, timeout, &mut on_chunk),
+            Framing::Packet { .. } => receive_packet(cc1101_device, timeout, &mut on_chunk),
+        };
+        let Some(signal) = received else {
+            continue;
+        };
+        heard = true;
+        for listener in listeners.iter_mut() {
+            let protocol = listener.protocol;
+            protocol.finish_stream(&mut listener.stream, &mut |res| {
+                queue_result(&mut results, &mut listener.outcome, protocol, res)
+            });
+        }
+        for (protocol, res) in results {
+            on_result(cc1101_device, protocol, &signal, res);
+        }
+        for listener in listeners.iter() {
+            let outcome = listener.outcome.unwrap_or(Err(DecodeError::NotEnoughData));
+            on_capture(
+                cc1101_device,
+                listener.protocol,
+                &signal,
+                &burst[..len],
+                outcome,
+            );
+        }
+    }
+    heard
+}
+
+/// Holds a decoder result until the burst is over, noting how the capture
+/// fared for `on_capture`.
+fn queue_result(
+    results: &mut BurstResults,
+    outcome: &mut Option<Result<(), DecodeError>>,
+    protocol: &'static dyn PowerProtocol,
+    res: Result<Reading, DecodeError>,
+) {
+    if outcome.is_none() || res.is_ok() {
+        *outcome = Some(res.as_ref().map(|_| ()).map_err(|&error| error));
+    }
+    if results.push((protocol, res)).is_err() {
+        error!("Too many packets in one burst, dropped one");
+    }
+}
+
+/// Appends a capture and what the radio made of it to `CAPTURE_LOG_PATH`.
+fn record_capture(
+    cc1101_device: &mut CC1101Device,
+    protocol: &dyn PowerProtocol,
+    signal: &Signal,
+    capture: &[u8],
+    outcome: Result<(), DecodeError>,
+) {
+    let record = CaptureRecord {
+        tick: unsafe { furi_get_tick() },
+        frequency_hz: cc1101_device.frequency_hz,
+        rssi_dbm: signal.rssi_dbm,
+        lqi: signal.lqi,
+        freq_est: signal.freq_est,
+        protocol: protocol.name(),
+        outcome,
+        data: capture,
+    };
+    files::append_file(CAPTURE_LOG_PATH, |write| {
+        record.write_line(&mut |text| write(text.as_bytes()))
+    });
+}
+
+/// Writes an oversampled capture to the SD card as a Sub-GHz RAW recording,
+/// which the stock app can replay. Packet-engine captures hold bytes rather
+/// than samples, so they're skipped.
+fn save_capture(protocol: &dyn PowerProtocol, capture: &[u8]) {
+    let profile = protocol.radio_profile();
+    if profile.framing != Framing::Raw {
+        return;
+    }
+    let path = format!(
+        "{}/powermon_{}.sub",
+        files::SUBGHZ_DIR.to_str().unwrap_or(""),
+        unsafe { furi_get_tick() }
+    );
+    let sample_us = 1_000_000.0 / profile.data_rate_baud;
+    let saved = files::write_subghz_file(path.as_c_str(), |write| {
+        write_sub(
+            profile.frequency_hz,
+            fsk_preset(profile.deviation_hz),
+            |on_pulse| bit_pulses(capture, sample_us, on_pulse),
+            &mut |text| write(text.as_bytes()),
+        )
+    });
+    if saved {
+        info!("Saved {}", path.as_c_str().to_str().unwrap_or("?"));
+    }
+}
+
+/// Waits up to `timeout_ticks` for carrier sense, then hands each read of the
+/// RX FIFO to `on_chunk` until the carrier drops. Returns the signal measured
+/// once the carrier settled, or `None` if no carrier was seen.
+fn receive_burst(
+    cc1101_device: &mut CC1101Device,
+    timeout_ticks: u32,
+    on_chunk: &mut dyn FnMut(&[u8]),
+) -> Option<Signal> {
+    let mut rx_buf = [0u8; 64];
+    cc1101_device.start_rx();
+    // Wait for GDO0 to be set -> carrier sense
+    if !cc1101_device.wait_for_gdo0(timeout_ticks) {
+        info!("Timeout1");
+        cc1101_device.spi_send_command(CMD::SIDLE);
+        return None;
+    }
     unsafe {
-        for _i in 0..10 {
-            cc1101_device.spi_send_command(CMD::SCAL);
-            furi_delay_tick(10);
-            cc1101_device.spi_send_command(CMD::SFRX);
-            cc1101_device.spi_send_command(CMD::SRX);
+        furi_delay_tick(10);
+        let signal = Signal::latch(cc1101_device);
+
+        while furi_hal_gpio_read(cc1101_device.subghz_gdo0) {
+            cc1101_device.sync_field(|dev| &mut dev.rx_bytes);
+            let rx_bytes1: usize = cc1101_device.rx_bytes.num_rxbytes() as usize;
+            cc1101_device.sync_field(|dev| &mut dev.rx_bytes);
+            let rx_bytes2: usize = cc1101_device.rx_bytes.num_rxbytes() as usize;
+
+            if rx_bytes1 == rx_bytes2 && rx_bytes1 > 0 && rx_bytes1 < 64 {
+                cc1101_device.spi_read_burst(0xC0 | 0x3F, &mut rx_buf[..rx_bytes1]);
+                on_chunk(&rx_buf[..rx_bytes1]);
+            } else if rx_bytes1 > 64 {
+                error!("RX Buffer Overflow")
+            } else {
+                continue;
+            }
+        }
+        cc1101_device.spi_send_command(CMD::SIDLE);
+        Some(signal)
+    }
+}
+
+/// Waits up to `timeout_ticks` for the packet engine to match a sync word, then
+/// hands the packet and its status bytes to `on_chunk`. Returns the signal
+/// measured last before the packet ended, once LQI covers as much of it as it
+/// can, or `None` if no sync word was seen.
+fn receive_packet(
+    cc1101_device: &mut CC1101Device,
+    timeout_ticks: u32,
+    on_chunk: &mut dyn FnMut(&[u8]),
+) -> Option<Signal> {
+    cc1101_device.start_rx();
+    if !cc1101_device.wait_for_gdo0(timeout_ticks) {
+        cc1101_device.spi_send_command(CMD::SIDLE);
+        return None;
+    }
+    let mut signal = Signal::latch(cc1101_device);
+    unsafe {
+        // The packet is complete once GDO0 drops again
+        let mut timeout = PACKET_TIMEOUT_TICKS;
+        while furi_hal_gpio_read(cc1101_device.subghz_gdo0) && timeout > 0 {
+            signal = Signal::latch(cc1101_device);
+            furi_delay_tick(1);
+            timeout -= 1;
+        }
+        cc1101_device.spi_send_command(CMD::SIDLE);
+    }
 
-            let mut timeout = 6000; // Wait 6 seconds
-            while !furi_hal_gpio_read(cc1101_device.subghz_gdo0) {
-                // Wait for GDO0 to be set -> carrier sense
-                furi_delay_tick(1);
-                if timeout == 0 {
-                    info!("Timeout1");
-                    break;
+    let mut packet = [0u8; 64];
+    cc1101_device.sync_field(|dev| &mut dev.rx_bytes);
+    let len = (cc1101_device.rx_bytes.num_rxbytes() as usize).min(packet.len());
+    if len == 0 {
+        return None;
+    }
+    cc1101_device.spi_read_burst(0xC0 | 0x3F, &mut packet[..len]);
+    on_chunk(&packet[..len]);
+    Some(signal)
+}
+
+/// Reports readings as they arrive until Back is pressed. Once every
+/// transmitter's period is learned, the radio only listens around each
+/// predicted transmission, for that transmitter's protocol, and sleeps in
+/// between.
+fn listen_until_back(cc1101_device: &mut CC1101Device, sensors: &mut SensorRegistry) -> i32 {
+    println!("Listening, hold Back to stop");
+    let mut schedule = TransmissionSchedule::new();
+    let wanted = wanted_protocols(sensors);
+    // The Back button pulls its pin low while held
+    while unsafe { furi_hal_gpio_read(&gpio_button_back) } {
+        let now = unsafe { furi_get_tick() };
+        schedule.expire(now);
+        let (timeout_ticks, expected) = match schedule.next_window() {
+            Some(window) => {
+                let wait_ticks = window.start_tick.wrapping_sub(now) as i32;
+                if wait_ticks > 0 {
+                    cc1101_device.sleep();
+                    unsafe { furi_delay_tick((wait_ticks as u32).min(BACK_POLL_TICKS)) };
+                    continue;
                 }
-                timeout -= 1;
-            }
-            furi_delay_tick(10);
-
-            let start_time = furi_get_tick();
-            if timeout != 0 {
-                let mut read_bytes: usize = 0;
-                let elapsed_ms = furi_get_tick() - start_time;
-                while elapsed_ms < 500
-                    && read_bytes < 127
-                    && furi_hal_gpio_read(cc1101_device.subghz_gdo0)
-                {
-                    cc1101_device.sync_field(|dev| &mut dev.rx_bytes);
-                    let mut rx_bytes1: usize = cc1101_device.rx_bytes.num_rxbytes() as usize;
-                    cc1101_device.sync_field(|dev| &mut dev.rx_bytes);
-                    let rx_bytes2: usize = cc1101_device.rx_bytes.num_rxbytes() as usize;
-
-                    if rx_bytes1 == rx_bytes2 && rx_bytes1 > 0 && rx_bytes1 < 64 {
-                        if read_bytes + rx_bytes1 >= 127 {
-                            rx_bytes1 = 127 - read_bytes;
+                let timeout_ticks = (window.end_tick.wrapping_sub(now) as i32).max(1) as u32;
+                (timeout_ticks, Some(window.key.protocol))
+            }
+            None => (BACK_POLL_TICKS, None),
+        };
+        cc1101_device.wake();
+        listen_captures(
+            cc1101_device,
+            timeout_ticks,
+            &|protocol| match expected {
+                Some(expected) => protocol.name() == expected,
+                None => wanted.contains(&protocol.name()),
+            },
+            |dev, protocol, signal, res| {
+                // Only packets that passed as received time the schedule
+                if let Some(reading) = res.as_ref().ok().filter(|reading| !reading.is_corrected()) {
+                    let key = reading.key();
+                    if sensors.accepts(key) {
+                        schedule.observe(key, unsafe { furi_get_tick() });
+                        if let Some(interval_ms) = schedule.interval_ms(key) {
+                            debug!(
+                                "{}:{:06X} transmits every {} ms",
+                                key.protocol, key.id, interval_ms as u32
+                            );
                         }
-                        cc1101_device.spi_read_burst(
-                            0xC0 | 0x3F,
-                            &mut rx_buf[read_bytes..read_bytes + rx_bytes1],
-                        );
-                        read_bytes += rx_bytes1;
-                    } else if rx_bytes1 > 64 {
-                        error!("RX Buffer Overflow")
-                    } else {
-                        continue;
                     }
                 }
-                cc1101_device.spi_send_command(CMD::SIDLE);
-                if read_bytes > 0 {
-                    let res = decode::decode_power(&rx_buf, read_bytes);
-                    match res {
-                        Ok(decode::DecodeResult {
-                            power_kw: power,
-                            packet: _,
-                            quality_metric: _,
-                        }) => println!("Power: {} W", (power * 1000.0) as u32),
-                        Err(decode::DecodeError::NotEnoughData) => println!("Incomplete packet"),
-                        Err(decode::DecodeError::PreambleNotFound) => {
-                            println!("Preamble not found")
-                        }
-                        Err(decode::DecodeError::SyncNotFound) => println!("Sync not found"),
-                        Err(decode::DecodeError::InsufficientSymbols) => {
-                            println!("Incomplete packet decoded")
-                        }
-                        Err(decode::DecodeError::ChecksumMismatch { expected, actual }) => {
-                            println!("Checksum mismatch ({} != {})", expected, actual)
-                        }
-                    }
+                handle_result(dev, sensors, protocol, signal, res)
+            },
+            |_, _, _, _, _| {},
+        );
+    }
+    cc1101_device.wake();
+    print_sensors(sensors);
+    0
+}
+
+/// Listens for `window_s` seconds, then offers the transmitters heard, strongest
+/// first, for the user to pair with.
+fn learn(cc1101_device: &mut CC1101Device, sensors: &mut SensorRegistry, window_s: u32) -> i32 {
+    // Ticks are milliseconds, and a u32 of them runs out after 49 days
+    let Some(window_ticks) = window_s.checked_mul(1000) else {
+        println!("Window must be under {} s", u32::MAX / 1000);
+        return 1;
+    };
+    println!("Listening for sensors for {} s", window_s);
+    let mut session = LearnSession::new();
+    let start = unsafe { furi_get_tick() };
+    loop {
+        let elapsed = unsafe { furi_get_tick() }.wrapping_sub(start);
+        if elapsed >= window_ticks {
+            break;
+        }
+        listen(
+            cc1101_device,
+            window_ticks - elapsed,
+            |_, _, signal, res| {
+                if let Ok(reading) = res {
+                    session.observe(&reading, signal.rssi_dbm);
+                }
+            },
+        );
+    }
+
+    let ranked = session.ranked();
+    if ranked.is_empty() {
+        println!("No sensors heard");
+        return 1;
+    }
+    for (rank, candidate) in ranked.iter().enumerate() {
+        println!(
+            "{}: {}:{:06X} {} dBm ({} packets)",
+            rank + 1,
+            candidate.key.protocol,
+            candidate.key.id,
+            candidate.mean_rssi_dbm() as i32,
+            candidate.packets
+        );
+    }
+
+    let mut dialogs = DialogsApp::open();
+    for candidate in ranked.iter() {
+        let text = format!(
+            "{} {:06X}\n{} dBm, {} packets",
+            candidate.key.protocol,
+            candidate.key.id,
+            candidate.mean_rssi_dbm() as i32,
+            candidate.packets
+        );
+        let mut message = DialogMessage::new();
+        message.set_header(c"Pair sensor?", 64, 0, Align::Center, Align::Top);
+        message.set_text(text.as_c_str(), 64, 32, Align::Center, Align::Center);
+        message.set_buttons(Some(c"Next"), None, Some(c"Pair"));
+        match dialogs.show_message(&message) {
+            DialogMessageButton::Right => {
+                if !sensors.set_paired(candidate.key, true) {
+                    print_no_room();
+                    return 1;
                 }
+                println!(
+                    "Paired sensor {}:{:06X}",
+                    candidate.key.protocol, candidate.key.id
+                );
+                return if sensors.save_to_sd() { 0 } else { 1 };
             }
+            DialogMessageButton::Back => break,
+            _ => {}
         }
     }
-    println!("Done, Exiting!");
+    println!("No sensor paired");
+    1
+}
+
+/// Derives a sensor's scale from a reference load of known power, averaging a
+/// few readings taken while only that load is running.
+fn calibrate(
+    cc1101_device: &mut CC1101Device,
+    sensors: &mut SensorRegistry,
+    id: Option<&str>,
+    reference_w: Option<&str>,
+) -> i32 {
+    let key = id.and_then(|id| sensors.resolve(id));
+    let reference_w = reference_w
+        .and_then(|word| word.parse::<f32>().ok())
+        .filter(|&reference_w| decode::is_positive(reference_w));
+    let (Some(key), Some(reference_w)) = (key, reference_w) else {
+        println!("Usage: calibrate <[protocol:]sensor id> <reference load W>");
+        return 1;
+    };
+    println!(
+        "Measuring sensor {}:{:06X}, keep the reference load running",
+        key.protocol, key.id
+    );
+
+    let calibration = sensors.calibration(key);
+    let mut readings = 0;
+    let mut total_kw = 0.0;
+    for _i in 0..CALIBRATION_ATTEMPTS {
+        if readings >= CALIBRATION_READINGS {
+            break;
+        }
+        listen(cc1101_device, 6000, |_, _, _, res| {
+            if let Ok(reading) = res {
+                if reading.key() == key {
+                    total_kw += calibration.apply(reading.power_kw);
+                    readings += 1;
+                }
+            }
+        });
+    }
+    if readings == 0 || total_kw <= 0.0 {
+        println!("No readings from sensor {}:{:06X}", key.protocol, key.id);
+        return 1;
+    }
+
+    let measured_kw = total_kw / readings as f32;
+    let Some(calibration) = calibration.calibrated_to(measured_kw, reference_w / 1000.0) else {
+        println!(
+            "Readings from sensor {}:{:06X} give no usable scale",
+            key.protocol, key.id
+        );
+        return 1;
+    };
+    if !sensors.set_calibration(key, calibration) {
+        print_no_room();
+        return 1;
+    }
+
+    let mut line: String<48> = String::new();
+    let _ = write!(
+        line,
+        "Sensor {}:{:06X} scale {:.4}",
+        key.protocol, key.id, calibration.scale_kw
+    );
+    println!("{}", line.as_str());
+    if !sensors.save_to_sd() {
+        return 1;
+    }
+    0
+}
+
+/// Tunes to the first channel of a sweep from `start_mhz` to `stop_mhz` in
+/// `step_khz` channels, defaulting to the 433 MHz ISM band, and enables
+/// calibration on every RX since channels stray too far from one calibration.
+/// Undo with `finish_sweep`.
+fn start_sweep(
+    cc1101_device: &mut CC1101Device,
+    start_mhz: Option<&str>,
+    stop_mhz: Option<&str>,
+    step_khz: Option<&str>,
+) -> Option<ScanPlan> {
+    let parse_hz = |word: Option<&str>, scale: f32, default: u32| match word {
+        Some(word) => word.parse::<f32>().ok().map(|value| (value * scale) as u32),
+        None => Some(default),
+    };
+    let (Some(start_hz), Some(stop_hz), Some(step_hz)) = (
+        parse_hz(start_mhz, 1_000_000.0, SCAN_START_HZ),
+        parse_hz(stop_mhz, 1_000_000.0, SCAN_STOP_HZ),
+        parse_hz(step_khz, 1_000.0, SCAN_STEP_HZ),
+    ) else {
+        println!("Expected [start MHz] [stop MHz] [step kHz]");
+        return None;
+    };
+
+    let profile = RadioProfile {
+        frequency_hz: start_hz,
+        ..Em422em.radio_profile()
+    };
+    if cc1101::Band::for_frequency(start_hz) != cc1101::Band::for_frequency(stop_hz)
+        || !configure_radio(cc1101_device, &profile)
+    {
+        println!("Sweep must lie within one band");
+        return None;
+    }
+    cc1101_device
+        .modem_config
+        .set_channel_spacing(step_hz as f32 / 1000.0);
+    cc1101_device.write_register(cc1101_device.modem_config);
+    let spacing_hz = (cc1101_device.modem_config.get_channel_spacing() * 1000.0) as u32;
+    let Some(plan) = ScanPlan::new(start_hz, stop_hz, spacing_hz) else {
+        println!("Too many channels, use a larger step");
+        return None;
+    };
+
+    cc1101_device.mcsm.set_fs_autocal(FS_AUTOCAL::IDLE_TO_RX_TX);
+    cc1101_device.write_register(cc1101_device.mcsm);
+    Some(plan)
+}
+
+/// Returns to channel 0 with calibration left to `ensure_calibrated`.
+fn finish_sweep(cc1101_device: &mut CC1101Device) {
+    cc1101_device.channr.set_chan(0);
+    cc1101_device.write_register(cc1101_device.channr);
+    cc1101_device.mcsm.set_fs_autocal(FS_AUTOCAL::NEVER);
+    cc1101_device.write_register(cc1101_device.mcsm);
+}
+
+/// Sweeps a range of channels, dwelling on each to sample RSSI and carrier
+/// sense, and tries the EM422EM decoder wherever there is a carrier. Reports
+/// the most likely centre frequency.
+fn scan(
+    cc1101_device: &mut CC1101Device,
+    start_mhz: Option<&str>,
+    stop_mhz: Option<&str>,
+    step_khz: Option<&str>,
+) -> i32 {
+    let Some(plan) = start_sweep(cc1101_device, start_mhz, stop_mhz, step_khz) else {
+        return 1;
+    };
+    let protocol = &Em422em;
+
+    println!(
+        "Scanning {} channels of {} Hz from {} Hz, taking {} s",
+        plan.channels,
+        plan.spacing_hz,
+        plan.base_hz,
+        plan.channels as u32 * SCAN_DWELL_TICKS / 1000
+    );
+    let mut results: heapless::Vec<ChannelActivity, MAX_SCAN_CHANNELS> = heapless::Vec::new();
+    for channel in 0..plan.channels {
+        let channel = channel as u8;
+        cc1101_device.channr.set_chan(channel);
+        cc1101_device.write_register(cc1101_device.channr);
+
+        let activity = sample_channel(cc1101_device, channel, protocol);
+        if activity.carrier {
+            println!(
+                "{} Hz: {} dBm, {} packets",
+                plan.frequency_hz(channel),
+                activity.peak_rssi_dbm as i32,
+                activity.packets
+            );
+        }
+        let _ = results.push(activity);
+    }
+
+    finish_sweep(cc1101_device);
+
+    match scan::best_centre_hz(&plan, &results) {
+        Some(centre_hz) => {
+            println!("Best centre frequency: {} Hz", centre_hz);
+            0
+        }
+        None => {
+            println!("No carrier found");
+            1
+        }
+    }
+}
+
+/// Listens on the current channel for `SCAN_DWELL_TICKS`, decoding each burst
+/// that raises carrier sense as it arrives and tracking the strongest one.
+fn sample_channel(
+    cc1101_device: &mut CC1101Device,
+    channel: u8,
+    protocol: &dyn PowerProtocol,
+) -> ChannelActivity {
+    let mut activity = ChannelActivity {
+        channel,
+        peak_rssi_dbm: f32::MIN,
+        carrier: false,
+        packets: 0,
+    };
+    let start = unsafe { furi_get_tick() };
+    loop {
+        let elapsed = unsafe { furi_get_tick() }.wrapping_sub(start);
+        if elapsed >= SCAN_DWELL_TICKS {
+            break;
+        }
+        let mut stream = CaptureStream::new();
+        let mut count = |res: Result<Reading, DecodeError>| {
+            if res.is_ok() {
+                activity.packets += 1;
+            }
+        };
+        let received = receive_burst(cc1101_device, SCAN_DWELL_TICKS - elapsed, &mut |chunk| {
+            protocol.decode_chunk(&mut stream, chunk, &mut count)
+        });
+        let Some(signal) = received else {
+            break;
+        };
+        protocol.finish_stream(&mut stream, &mut count);
+        activity.carrier = true;
+        activity.peak_rssi_dbm = activity.peak_rssi_dbm.max(signal.rssi_dbm);
+    }
+    activity
+}
+
+/// Draws a live spectrum of a range of channels until Back is pressed: a bar
+/// per channel for the latest sweep, the strongest marked, above a waterfall
+/// of earlier sweeps.
+fn spectrum(
+    cc1101_device: &mut CC1101Device,
+    start_mhz: Option<&str>,
+    stop_mhz: Option<&str>,
+    step_khz: Option<&str>,
+) -> i32 {
+    let Some(plan) = start_sweep(cc1101_device, start_mhz, stop_mhz, step_khz) else {
+        return 1;
+    };
+
+    let gui = Gui::open();
+    let mut canvas = gui.direct_draw_acquire();
+    let mut spectrum = Spectrum::new();
+    let mut sweep = [0f32; MAX_SCAN_CHANNELS];
+    let sweep = &mut sweep[..plan.channels as usize];
+    // The Back button pulls its pin low while held
+    while unsafe { furi_hal_gpio_read(&gpio_button_back) } {
+        for (channel, rssi_dbm) in sweep.iter_mut().enumerate() {
+            cc1101_device.spi_send_command(CMD::SIDLE);
+            cc1101_device.channr.set_chan(channel as u8);
+            cc1101_device.write_register(cc1101_device.channr);
+            cc1101_device.spi_send_command(CMD::SRX);
+            unsafe { furi_delay_tick(SPECTRUM_SETTLE_TICKS) };
+            cc1101_device.sync_field(|dev| &mut dev.rssi);
+            *rssi_dbm = cc1101_device.rssi.rssi_dbm();
+        }
+        cc1101_device.spi_send_command(CMD::SIDLE);
+
+        spectrum.push_sweep(sweep);
+        draw_spectrum(&mut canvas, &plan, &spectrum, sweep);
+    }
+
+    drop(canvas);
+    finish_sweep(cc1101_device);
+    0
+}
+
+fn draw_spectrum(canvas: &mut Canvas, plan: &ScanPlan, spectrum: &Spectrum, sweep: &[f32]) {
+    let raw = canvas.as_ptr();
+    canvas.clear();
+
+    let peak = spectrum.peak();
+    let peak_step = peak * sweep.len() / SPECTRUM_WIDTH;
+    let header = format!(
+        "{} kHz {} dBm",
+        plan.frequency_hz(peak_step as u8) / 1000,
+        sweep[peak_step] as i32
+    );
+    unsafe {
+        sys::canvas_set_font(raw, sys::FontSecondary);
+        sys::canvas_draw_str(raw, 0, 7, header.as_c_str().as_ptr());
+
+        let baseline = 8 + BAR_HEIGHT as i32;
+        for x in 0..SPECTRUM_WIDTH {
+            let height = spectrum.bar(x) as usize;
+            if height > 0 {
+                sys::canvas_draw_box(raw, x as i32, baseline - height as i32, 1, height);
+            }
+            for age in 0..WATERFALL_ROWS {
+                if spectrum.waterfall_pixel(age, x) {
+                    sys::canvas_draw_dot(raw, x as i32, baseline + age as i32);
+                }
+            }
+        }
+        sys::canvas_draw_line(raw, peak as i32, 8, peak as i32, baseline - 1);
+    }
+    canvas.commit();
+}
+
+/// Round trips a reading through each protocol's encoder and decoder.
+fn self_test() -> i32 {
+    let mut failures = 0;
+    let mut capture = [0u8; BURST_LEN];
+    for &protocol in PROTOCOLS {
+        let reading = Reading {
+            protocol: protocol.name(),
+            sensor_id: 0x123456,
+            power_kw: 1.0,
+            quality: 0,
+            corrected_bits: 0,
+            baud_rate: None,
+            energy_kwh: None,
+        };
+        let Some(len) = protocol.encode(&reading, &mut capture) else {
+            println!("{}: no encoder", protocol.name());
+            continue;
+        };
+        match protocol.decode(&capture[..len]) {
+            Ok(decoded)
+                if decoded.sensor_id == reading.sensor_id
+                    && (decoded.power_kw - reading.power_kw).abs() < 0.01 =>
+            {
+                println!("{}: ok", protocol.name())
+            }
+            _ => {
+                println!("{}: FAILED", protocol.name());
+                failures += 1;
+            }
+        }
+    }
+    if failures == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+/// Decodes a Sub-GHz app RAW recording, streaming it from the SD card twice:
+/// once to find the preamble and again to decode.
+fn import_sub(sensors: &SensorRegistry, path: Option<&str>) -> i32 {
+    let Some(path) = path else {
+        println!("Usage: import <path to .sub file>");
+        return 1;
+    };
+    let path = format!("{}", path);
+    let mut chunk = [0u8; IMPORT_CHUNK_LEN];
+    let readable = Cell::new(true);
+    let raw = Cell::new(true);
+    let am = Cell::new(false);
+    let usable = || readable.get() && raw.get() && !am.get();
+    Em422em.decode_pulse_stream(
+        |on_pulse| {
+            if !usable() {
+                return;
+            }
+            let mut parser = SubParser::new();
+            readable.set(files::read_chunks(path.as_c_str(), &mut chunk, |bytes| {
+                parser.feed(bytes, on_pulse)
+            }));
+            let header = parser.finish(on_pulse);
+            raw.set(header.raw);
+            am.set(header.is_am());
+            if let Some(frequency_hz) = header.frequency_hz.filter(|_| usable()) {
+                info!("Recorded at {} Hz", frequency_hz);
+            }
+        },
+        &mut |res| {
+            if usable() {
+                print_result(sensors, res)
+            }
+        },
+    );
+    if !readable.get() {
+        println!("Can't read {}", path.as_c_str().to_str().unwrap_or("?"));
+        return 1;
+    }
+    if !raw.get() {
+        println!("Not a RAW recording");
+        return 1;
+    }
+    if am.get() {
+        println!("Recorded with an AM preset, record with 2-FSK instead");
+        return 1;
+    }
+    0
+}
+
+fn name_sensor(sensors: &mut SensorRegistry, id: Option<&str>, name: Option<&str>) -> i32 {
+    let (Some(key), Some(name)) = (id.and_then(|id| sensors.resolve(id)), name) else {
+        println!("Usage: name <[protocol:]sensor id> <name>");
+        return 1;
+    };
+    if !sensors.has_room_for(key) {
+        print_no_room();
+        return 1;
+    }
+    if !sensors.set_name(key, name) {
+        println!(
+            "Name is longer than {} characters",
+            sensors::SENSOR_NAME_LEN
+        );
+        return 1;
+    }
+    if !sensors.save_to_sd() {
+        return 1;
+    }
+    println!("Sensor {}:{:06X} named {}", key.protocol, key.id, name);
+    0
+}
+
+fn pair_sensor(sensors: &mut SensorRegistry, id: Option<&str>, paired: bool) -> i32 {
+    let Some(key) = id.and_then(|id| sensors.resolve(id)) else {
+        println!("Usage: pair|unpair <[protocol:]sensor id>");
+        return 1;
+    };
+    if !sensors.set_paired(key, paired) {
+        print_no_room();
+        return 1;
+    }
+    if !sensors.save_to_sd() {
+        return 1;
+    }
+    0
+}
 
+fn configure_sensor<'a>(
+    sensors: &mut SensorRegistry,
+    id: Option<&str>,
+    settings: impl Iterator<Item = &'a str>,
+) -> i32 {
+    let Some(key) = id.and_then(|id| sensors.resolve(id)) else {
+        println!(
+            "Usage: set <[protocol:]sensor id> [scale=<kW>] [voltage=<V|none>] [pf=<pf|none>]"
+        );
+        return 1;
+    };
+    if !sensors.has_room_for(key) {
+        print_no_room();
+        return 1;
+    }
+    for setting in settings {
+        if !sensors.apply_setting(key, setting) {
+            println!("Invalid setting {}", setting);
+            return 1;
+        }
+    }
+    if !sensors.save_to_sd() {
+        return 1;
+    }
     0
 }
+
+/// Sensors the user has configured are never dropped to make room.
+fn print_no_room() {
+    println!(
+        "All {} sensor slots hold configured sensors",
+        sensors::MAX_SENSORS
+    );
+}
+
+fn select_sensor(sensors: &mut SensorRegistry, id: Option<&str>) -> i32 {
+    let key = match id {
+        Some("all") => None,
+        Some(id) if sensors.resolve(id).is_some() => sensors.resolve(id),
+        _ => {
+            println!("Usage: select <[protocol:]sensor id|all>");
+            return 1;
+        }
+    };
+    sensors.select(key);
+    if !sensors.save_to_sd() {
+        return 1;
+    }
+    0
+}
+
+fn set_repair(sensors: &mut SensorRegistry, setting: Option<&str>) -> i32 {
+    let repair = match setting {
+        Some("on") => true,
+        Some("off") => false,
+        _ => {
+            println!("Usage: repair <on|off>");
+            return 1;
+        }
+    };
+    sensors.set_repair(repair);
+    if !sensors.save_to_sd() {
+        return 1;
+    }
+    0
+}
+
+fn print_sensors(sensors: &SensorRegistry) {
+    let now = unsafe { furi_get_tick() };
+    for sensor in sensors.iter() {
+        let selected = if sensors.selected() == Some(sensor.key) {
+            "*"
+        } else {
+            " "
+        };
+        println!(
+            "{}{}:{:06X} {}: {} W, {} dBm, {}% ok, {} repaired, seen {} s ago",
+            selected,
+            sensor.key.protocol,
+            sensor.key.id,
+            sensor.name.as_str(),
+            (sensor.last_power_kw * 1000.0) as u32,
+            sensor.last_rssi_dbm as i32,
+            (sensor.success_rate() * 100.0) as u32,
+            sensor.packets_corrected,
+            now.wrapping_sub(sensor.last_seen_tick) / 1000
+        );
+    }
+}
+
+/// Updates the sensor registry with a decoder result and reports it, skipping
+/// readings from sensors other than the selected one.
+fn handle_result(
+    cc1101_device: &mut CC1101Device,
+    sensors: &mut SensorRegistry,
+    protocol: &dyn PowerProtocol,
+    signal: &Signal,
+    res: Result<Reading, DecodeError>,
+) {
+    let res = match res {
+        Err(error) if sensors.repairs() => protocol
+            .repair(&error, &|reading| sensors.is_plausible(reading))
+            .ok_or(error),
+        res => res,
+    };
+    match &res {
+        Ok(reading) if !sensors.accepts(reading.key()) => return,
+        Ok(reading) => {
+            let now = unsafe { furi_get_tick() };
+            let key = reading.key();
+            let tracked = sensors.record(reading, signal.rssi_dbm, now).is_some();
+            if tracked && !reading.is_corrected() {
+                track_freq_offset(cc1101_device, sensors, key, signal);
+            }
+            if !sensors.is_shown(key) {
+                return;
+            }
+        }
+        Err(error) => {
+            if let Some(id) = protocol.claimed_sensor_id(error) {
+                sensors.record_failure(SensorKey {
+                    protocol: protocol.name(),
+                    id,
+                });
+            }
+        }
+    }
+    print_result(sensors, res);
+}
+
+/// Folds a packet's carrier offset, as measured while it arrived, into its
+/// sensor's average, and retunes to that average when it's the sensor the
+/// receiver follows, so the channel filter stays centred on it. CHANBW stays
+/// as set up: the filter is already narrower than an EM422EM signal, so the
+/// gain is in centring rather than narrowing it.
+fn track_freq_offset(
+    cc1101_device: &mut CC1101Device,
+    sensors: &mut SensorRegistry,
+    key: SensorKey,
+    signal: &Signal,
+) {
+    let offset = signal.freq_offset as f32 + signal.freq_est as f32;
+    let Some(average) = sensors.record_freq_offset(key, offset) else {
+        return;
+    };
+    let average = libm::roundf(average).clamp(i8::MIN as f32, i8::MAX as f32) as i8;
+    if sensors.tuned_sensor() == Some(key) && average != cc1101_device.freq_offset() {
+        debug!(
+            "Retuning {} steps for {}:{:06X}",
+            average as i32, key.protocol, key.id
+        );
+        cc1101_device.set_freq_offset(average);
+    }
+}
+
+fn print_result(sensors: &SensorRegistry, res: Result<Reading, DecodeError>) {
+    match res {
+        Ok(reading) => {
+            let name = sensors
+                .get(reading.key())
+                .map_or("", |sensor| sensor.name.as_str());
+            let power_kw = sensors.calibration(reading.key()).apply(reading.power_kw);
+            println!(
+                "Sensor {:06X} {} ({}): Power: {} W, quality {}{}",
+                reading.sensor_id,
+                name,
+                reading.protocol,
+                (power_kw * 1000.0) as u32,
+                reading.quality,
+                if reading.is_corrected() {
+                    ", repaired"
+                } else {
+                    ""
+                }
+            );
+            if let Some(energy_kwh) = reading.energy_kwh {
+                println!("Energy: {} Wh", (energy_kwh * 1000.0) as u32);
+            }
+            if let Some(baud_rate) = reading.baud_rate {
+                debug!("Measured {} baud", baud_rate as u32);
+            }
+        }
+        Err(decode::DecodeError::NotEnoughData) => println!("Incomplete packet"),
+        Err(decode::DecodeError::PreambleNotFound) => {
+            println!("Preamble not found")
+        }
+        Err(decode::DecodeError::SyncNotFound) => println!("Sync not found"),
+        Err(decode::DecodeError::InsufficientSymbols) => {
+            println!("Incomplete packet decoded")
+        }
+        Err(decode::DecodeError::ChecksumMismatch {
+            expected,
+            actual,
+            confidence,
+            ..
+        }) => {
+            let [a, b, c] = confidence.least_certain::<3>();
+            println!(
+                "Checksum mismatch ({} != {}), least certain bits {} {} {}",
+                expected, actual, a, b, c
+            )
+        }
+        Err(decode::DecodeError::IntegrityMismatch {
+            expected, actual, ..
+        }) => println!("Check value mismatch ({} != {})", expected, actual),
+    }
+}
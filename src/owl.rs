@@ -0,0 +1,251 @@
+use crate::decode::{self, DecodeError, Polarity};
+use crate::protocol::{Framing, PowerProtocol, RadioProfile, Reading};
+
+/// Fewest preamble ones accepted before the sync nibble.
+const PREAMBLE_MIN_BITS: usize = 16;
+/// The sync nibble 0xA, sent LSB first.
+const SYNC_BITS: [u8; 4] = [0, 1, 0, 1];
+/// Frame length without and with the energy counter, checksum included.
+const SHORT_FRAME_BYTES: usize = 6;
+const LONG_FRAME_BYTES: usize = 12;
+/// Decoded bits held at once, enough for a long preamble and a long frame.
+const MAX_FRAME_BITS: usize = 256;
+const WATT_SECONDS_PER_KWH: f32 = 3_600_000.0;
+
+/// A frame from an OWL CM180-family transmitter, which uses Oregon Scientific's
+/// v3 framing. Bytes go over the air LSB first; the layout follows rtl_433's
+/// decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwlPacket {
+    /// Sensor type from bytes 0-1.
+    pub sensor_type: u16,
+    /// Rolling ID from byte 2, which changes when the batteries do.
+    pub rolling_id: u8,
+    /// Instantaneous power in watts from bytes 3-4, low nibble unused.
+    pub power_w: u16,
+    /// Cumulative energy in watt-seconds from bytes 5-10, only in long frames.
+    pub energy_ws: Option<u64>,
+}
+
+impl OwlPacket {
+    /// Parses a short or long frame, checksum byte excluded.
+    pub fn from_bytes(frame: &[u8]) -> Self {
+        let energy_ws = (frame.len() >= LONG_FRAME_BYTES - 1).then(|| {
+            frame[5..11]
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &byte| acc << 8 | byte as u64)
+        });
+        Self {
+            sensor_type: u16::from_be_bytes([frame[0], frame[1]]),
+            rolling_id: frame[2],
+            power_w: u16::from_be_bytes([frame[4], frame[3] & 0xF0]),
+            energy_ws,
+        }
+    }
+
+    /// Sum of the nibbles before the checksum byte, modulo 256.
+    pub fn expected_checksum(frame: &[u8]) -> u8 {
+        frame.iter().fold(0u8, |acc, &byte| {
+            acc.wrapping_add(byte >> 4).wrapping_add(byte & 0x0F)
+        })
+    }
+
+    /// Type and rolling ID together, so a battery change looks like a new sensor.
+    pub fn sensor_id(&self) -> u32 {
+        (self.sensor_type as u32) << 8 | self.rolling_id as u32
+    }
+}
+
+pub struct Owl;
+
+impl Owl {
+    /// Manchester chips arrive at 2048 per second, and the radio samples each
+    /// once so the software decoder sees one bit per chip.
+    const PROFILE: RadioProfile = RadioProfile {
+        frequency_hz: 433_920_000,
+        data_rate_baud: 2_048.0,
+        deviation_hz: 20_000.0,
+        framing: Framing::Raw,
+    };
+
+    /// Finds the first bit of the frame in a run of decoded bits, just after a
+    /// preamble and sync nibble.
+    fn frame_start(bits: &[u8], len: usize) -> Option<usize> {
+        let mut ones = 0;
+        for idx in 0..len {
+            if decode::bit_at(bits, idx) == 1 {
+                ones += 1;
+                continue;
+            }
+            let end = idx + SYNC_BITS.len();
+            if ones >= PREAMBLE_MIN_BITS
+                && end <= len
+                && SYNC_BITS
+                    .iter()
+                    .enumerate()
+                    .all(|(i, &bit)| decode::bit_at(bits, idx + i) == bit)
+            {
+                return Some(end);
+            }
+            ones = 0;
+        }
+        None
+    }
+
+    /// Parses the frame at `start`, preferring the long form when its checksum
+    /// holds.
+    fn parse(&self, bits: &[u8], start: usize, len: usize) -> Result<Reading, DecodeError> {
+        let mut frame = [0u8; LONG_FRAME_BYTES];
+        let available = ((len - start) / 8).min(LONG_FRAME_BYTES);
+        for (byte_idx, byte) in frame[..available].iter_mut().enumerate() {
+            for bit in 0..8 {
+                *byte |= decode::bit_at(bits, start + byte_idx * 8 + bit) << bit;
+            }
+        }
+        if available < SHORT_FRAME_BYTES {
+            return Err(DecodeError::InsufficientSymbols);
+        }
+
+        let mut mismatch = None;
+        for frame_len in [LONG_FRAME_BYTES, SHORT_FRAME_BYTES] {
+            if frame_len > available {
+                continue;
+            }
+            let (body, checksum) = (&frame[..frame_len - 1], frame[frame_len - 1]);
+            let expected = OwlPacket::expected_checksum(body);
+            if checksum == expected {
+                let fields = OwlPacket::from_bytes(body);
+                return Ok(Reading {
+                    protocol: self.name(),
+                    sensor_id: fields.sensor_id(),
+                    power_kw: fields.power_w as f32 / 1000.0,
+                    quality: u8::MAX,
+                    corrected_bits: 0,
+                    baud_rate: None,
+                    energy_kwh: fields
+                        .energy_ws
+                        .map(|energy| energy as f32 / WATT_SECONDS_PER_KWH),
+                });
+            }
+            // Report the short form, whose header is all claimed_sensor_id reads
+            let mut packet = [0u8; 8];
+            packet.copy_from_slice(&frame[..8]);
+            mismatch = Some(DecodeError::IntegrityMismatch {
+                expected: expected.into(),
+                actual: checksum.into(),
+                packet,
+            });
+        }
+        Err(mismatch.unwrap_or(DecodeError::InsufficientSymbols))
+    }
+}
+
+impl PowerProtocol for Owl {
+    fn name(&self) -> &'static str {
+        "OWL"
+    }
+
+    fn radio_profile(&self) -> RadioProfile {
+        Self::PROFILE
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Reading, DecodeError> {
+        if data.is_empty() {
+            return Err(DecodeError::NotEnoughData);
+        }
+
+        let mut bits = [0u8; MAX_FRAME_BITS / 8];
+        let mut furthest = DecodeError::SyncNotFound;
+        for offset in 0..2 {
+            for polarity in [Polarity::Normal, Polarity::Inverted] {
+                let mut start = offset;
+                while start + 1 < data.len() * 8 {
+                    let len = decode::manchester_decode(data, start, polarity, &mut bits);
+                    // Skip past the invalid pair that ended this run
+                    start += 2 * (len + 1);
+                    let Some(frame) = Self::frame_start(&bits, len) else {
+                        continue;
+                    };
+                    match self.parse(&bits, frame, len) {
+                        Ok(reading) => return Ok(reading),
+                        Err(err @ DecodeError::IntegrityMismatch { .. }) => furthest = err,
+                        Err(err) => {
+                            if furthest == DecodeError::SyncNotFound {
+                                furthest = err;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Err(furthest)
+    }
+
+    fn claimed_sensor_id(&self, error: &DecodeError) -> Option<u32> {
+        match error {
+            DecodeError::IntegrityMismatch { packet, .. } => {
+                Some(OwlPacket::from_bytes(&packet[..SHORT_FRAME_BYTES - 1]).sensor_id())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{lsb_first, manchester, pack};
+
+    /// Lays out a frame as chips, one sample each, after `lead` idle samples.
+    fn synthesize(frame: &[u8], lead: usize, invert: bool) -> std::vec::Vec<u8> {
+        let mut bits = std::vec![1u8; 24];
+        bits.extend_from_slice(&SYNC_BITS);
+        bits.extend(lsb_first(frame));
+        let mut chips = std::vec![0u8; lead];
+        chips.extend(manchester(bits));
+        chips.extend_from_slice(&[0; 16]);
+        pack(&chips, invert)
+    }
+
+    fn frame(power_w: u16, energy_ws: Option<u64>) -> std::vec::Vec<u8> {
+        let power = power_w.to_be_bytes();
+        let mut frame = std::vec![0x2C, 0x40, 0x9A, power[1] & 0xF0, power[0]];
+        if let Some(energy) = energy_ws {
+            frame.extend_from_slice(&energy.to_le_bytes()[..6]);
+        }
+        frame.push(OwlPacket::expected_checksum(&frame));
+        frame
+    }
+
+    #[test]
+    fn decodes_long_frames() {
+        let reading = Owl
+            .decode(&synthesize(&frame(1520, Some(36_000_000)), 5, false))
+            .unwrap();
+        assert_eq!(reading.sensor_id, 0x2C409A);
+        assert!((reading.power_kw - 1.52).abs() < 1e-6);
+        assert_eq!(reading.energy_kwh, Some(10.0));
+    }
+
+    #[test]
+    fn decodes_short_frames_either_polarity() {
+        for (lead, invert) in [(8, false), (3, true)] {
+            let reading = Owl
+                .decode(&synthesize(&frame(272, None), lead, invert))
+                .unwrap();
+            assert!((reading.power_kw - 0.272).abs() < 1e-6);
+            assert_eq!(reading.energy_kwh, None);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut bad = frame(1520, None);
+        bad[3] ^= 0x10;
+        let err = Owl.decode(&synthesize(&bad, 4, false)).unwrap_err();
+        assert!(matches!(err, DecodeError::IntegrityMismatch { .. }));
+        assert_eq!(Owl.claimed_sensor_id(&err), Some(0x2C409A));
+        assert_eq!(Owl.decode(&[0u8; 32]), Err(DecodeError::SyncNotFound));
+    }
+}
@@ -0,0 +1,524 @@
+use heapless::Vec;
+
+use crate::currentcost::CurrentCost;
+use crate::decode::{self, Calibration, DecodeError, DecodeResult, Em422Packet, StreamDecoder};
+use crate::efergy::Efergy;
+use crate::owl::Owl;
+use crate::pulse::{PreambleFinder, Pulse, Resampler, PREAMBLE_RUN_SAMPLES};
+
+/// How the CC1101 must be set up to receive a protocol.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RadioProfile {
+    pub frequency_hz: u32,
+    /// Rate the radio samples the demodulated signal at, which may be a
+    /// multiple of the transmitter's symbol rate.
+    pub data_rate_baud: f32,
+    pub deviation_hz: f32,
+    pub framing: Framing,
+}
+
+/// Profiles whose frequencies are this close share a listen, since the channel
+/// filter passes both.
+#[cfg(any(test, target_os = "none"))]
+const SHARED_CHANNEL_HZ: u32 = 25_000;
+/// Bytes a protocol without a streaming decoder gathers before decoding them.
+const STREAM_BUF_LEN: usize = 256;
+
+impl RadioProfile {
+    /// Whether one listen with either profile receives both protocols, which
+    /// then needn't take turns at the radio.
+    #[cfg(any(test, target_os = "none"))]
+    pub fn shares_channel(&self, other: &RadioProfile) -> bool {
+        self.framing == other.framing
+            && self.data_rate_baud == other.data_rate_baud
+            && self.deviation_hz == other.deviation_hz
+            && self.frequency_hz.abs_diff(other.frequency_hz) <= SHARED_CHANNEL_HZ
+    }
+}
+
+/// How the radio delimits what it hands to the decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Stream every demodulated bit while carrier sense holds, and leave
+    /// finding packets to the decoder.
+    Raw,
+    /// Let the packet engine wait for `sync_word` and capture `length` bytes.
+    /// The decoder gets the packet followed by the RSSI and LQI status bytes,
+    /// and checks the packet's integrity itself.
+    Packet { sync_word: u16, length: u8 },
+}
+
+/// A power reading from any supported transmitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    /// Name of the protocol that decoded the packet.
+    pub protocol: &'static str,
+    pub sensor_id: u32,
+    /// Power as the protocol reports it, before per-sensor calibration.
+    pub power_kw: f32,
+    /// How cleanly the packet was received, 0-255.
+    pub quality: u8,
+    /// Bits flipped to make the packet pass its integrity check.
+    pub corrected_bits: u8,
+    /// Transmitter baud rate, for decoders that measure it.
+    pub baud_rate: Option<f32>,
+    /// Cumulative energy, for transmitters that keep a running total.
+    pub energy_kwh: Option<f32>,
+}
+
+impl Reading {
+    #[cfg(any(test, target_os = "none"))]
+    pub fn is_corrected(&self) -> bool {
+        self.corrected_bits > 0
+    }
+
+    #[cfg(any(test, target_os = "none"))]
+    pub fn key(&self) -> SensorKey {
+        SensorKey {
+            protocol: self.protocol,
+            id: self.sensor_id,
+        }
+    }
+}
+
+/// Names a transmitter. Brands pick IDs independently, so the same ID can turn
+/// up under two protocols.
+#[cfg(any(test, target_os = "none"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorKey {
+    /// Name of the protocol, as `PowerProtocol::name` gives it.
+    pub protocol: &'static str,
+    pub id: u32,
+}
+
+/// A capture's decoding state while it arrives from the radio in chunks.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStream {
+    decoder: StreamDecoder,
+    /// Bytes waiting for `decode_all`, for protocols that don't stream.
+    pending: Vec<u8, STREAM_BUF_LEN>,
+    decoded: usize,
+    error: Option<DecodeError>,
+}
+
+impl CaptureStream {
+    #[cfg(any(test, target_os = "none"))]
+    pub const fn new() -> Self {
+        Self {
+            decoder: StreamDecoder::new(),
+            pending: Vec::new(),
+            decoded: 0,
+            error: None,
+        }
+    }
+
+    /// Decodes the waiting bytes with `decode_all`, holding back errors for
+    /// `finish_stream`. Mid-capture, bytes that decoded nothing are kept in
+    /// part, in case a packet runs on into the next chunk.
+    fn flush(
+        &mut self,
+        decode_all: impl FnOnce(&[u8], &mut dyn FnMut(Result<Reading, DecodeError>)),
+        last: bool,
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        let decoded_before = self.decoded;
+        decode_all(&self.pending, &mut |res| match res {
+            Ok(reading) => {
+                self.decoded += 1;
+                on_result(Ok(reading));
+            }
+            Err(error) => {
+                self.error.get_or_insert(error);
+            }
+        });
+        if last || self.decoded > decoded_before {
+            self.pending.clear();
+        } else {
+            let keep = self.pending.len() / 2;
+            let start = self.pending.len() - keep;
+            self.pending.copy_within(start.., 0);
+            self.pending.truncate(keep);
+        }
+    }
+}
+
+/// A clamp brand's over-the-air format.
+// The host decoder only decodes, so the radio side goes unused there
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub trait PowerProtocol: Sync {
+    fn name(&self) -> &'static str;
+
+    fn radio_profile(&self) -> RadioProfile;
+
+    /// Decodes the first packet in a capture.
+    fn decode(&self, data: &[u8]) -> Result<Reading, DecodeError>;
+
+    /// Decodes every packet in a capture. Protocols whose packets can arrive
+    /// back to back override this.
+    fn decode_all(&self, data: &[u8], on_result: &mut dyn FnMut(Result<Reading, DecodeError>)) {
+        on_result(self.decode(data));
+    }
+
+    /// Decodes the next chunk of a capture as the radio delivers it, reporting
+    /// packets as soon as they're complete. Protocols without a streaming
+    /// decoder gather chunks and run `decode_all` whenever the buffer fills.
+    fn decode_chunk(
+        &self,
+        stream: &mut CaptureStream,
+        chunk: &[u8],
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        for &byte in chunk {
+            if stream.pending.push(byte).is_err() {
+                stream.flush(|data, on| self.decode_all(data, on), false, on_result);
+                let _ = stream.pending.push(byte);
+            }
+        }
+    }
+
+    /// Decodes what's left once the capture ends, and reports why it failed
+    /// if nothing in it decoded.
+    fn finish_stream(
+        &self,
+        stream: &mut CaptureStream,
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        stream.flush(|data, on| self.decode_all(data, on), true, on_result);
+        if stream.decoded == 0 {
+            if let Some(error) = stream.error.take() {
+                on_result(Err(error));
+            }
+        }
+    }
+
+    /// Tries to recover a packet that failed its integrity check, accepting
+    /// only a candidate that `plausible` vouches for.
+    fn repair(
+        &self,
+        _error: &DecodeError,
+        _plausible: &dyn Fn(&Reading) -> bool,
+    ) -> Option<Reading> {
+        None
+    }
+
+    /// The sensor a corrupt packet claims to come from, if its header can be
+    /// read at all.
+    fn claimed_sensor_id(&self, _error: &DecodeError) -> Option<u32> {
+        None
+    }
+
+    /// Writes the capture a transmitter would produce for `reading`, returning
+    /// its length, for protocols we know how to generate.
+    fn encode(&self, _reading: &Reading, _out: &mut [u8]) -> Option<usize> {
+        None
+    }
+}
+
+/// Number of protocols in `PROTOCOLS`.
+pub const PROTOCOL_COUNT: usize = 4;
+
+/// Every protocol the receive loop listens for, in the order it tries them.
+pub static PROTOCOLS: &[&dyn PowerProtocol; PROTOCOL_COUNT] =
+    &[&Em422em, &Efergy, &Owl, &CurrentCost];
+
+/// The registered protocol called `name`, ignoring case.
+#[cfg(any(test, target_os = "none"))]
+pub fn protocol_named(name: &str) -> Option<&'static dyn PowerProtocol> {
+    PROTOCOLS
+        .iter()
+        .copied()
+        .find(|protocol| protocol.name().eq_ignore_ascii_case(name))
+}
+
+/// The EM422EM clamp this app was written for.
+pub struct Em422em;
+
+impl Em422em {
+    /// The radio samples at twice the transmitter's nominal rate.
+    const PROFILE: RadioProfile = RadioProfile {
+        frequency_hz: 433_535_649,
+        data_rate_baud: 16_150.0 * 2.0,
+        deviation_hz: 84_000.0 / 2.0,
+        framing: Framing::Raw,
+    };
+
+    /// `sample_rate_hz` is the rate the decoded bit stream was sampled at.
+    fn reading(result: &DecodeResult, sample_rate_hz: f32) -> Reading {
+        Reading {
+            protocol: Self.name(),
+            sensor_id: result.fields().transmitter_id,
+            power_kw: result.power_kw,
+            quality: result.packet_quality(),
+            corrected_bits: result.corrected_bits,
+            baud_rate: Some(result.symbol_period.baud_rate(sample_rate_hz)),
+            energy_kwh: None,
+        }
+    }
+
+    /// Reports why the tail of a capture failed, but only if nothing in it
+    /// decoded.
+    fn finish(
+        decoder: &mut StreamDecoder,
+        decoded: usize,
+        sample_rate_hz: f32,
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        let result = decoder.finish();
+        if decoded == 0 || result.is_ok() {
+            on_result(result.map(|result| Self::reading(&result, sample_rate_hz)));
+        }
+    }
+
+    /// Decodes every packet in a capture of pulse lengths, as asynchronous
+    /// serial mode or a Sub-GHz RAW recording gives. The preamble sets the
+    /// sample rate, so the transmitter needn't match the radio profile's rate.
+    pub fn decode_pulses(
+        &self,
+        pulses: &[Pulse],
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        self.decode_pulse_stream(
+            |on_pulse| pulses.iter().for_each(|&pulse| on_pulse(pulse)),
+            on_result,
+        );
+    }
+
+    /// Like `decode_pulses`, for captures too long to hold at once. `replay`
+    /// feeds the whole capture to its argument, and is called twice: once to
+    /// measure the preamble and once to decode.
+    pub fn decode_pulse_stream(
+        &self,
+        mut replay: impl FnMut(&mut dyn FnMut(Pulse)),
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        let mut finder = PreambleFinder::new();
+        replay(&mut |pulse| finder.push(pulse));
+        let Some(run_us) = finder.run_us() else {
+            return on_result(Err(DecodeError::PreambleNotFound));
+        };
+        let sample_us = run_us / PREAMBLE_RUN_SAMPLES as f32;
+        let sample_rate_hz = 1_000_000.0 / sample_us;
+
+        let mut resampler = Resampler::new(sample_us);
+        let mut decoder = StreamDecoder::new();
+        let mut decoded = 0;
+        replay(&mut |pulse| {
+            resampler.push(pulse, |bit| {
+                if let Some(result) = decoder.push_bit(bit) {
+                    on_result(result.map(|result| Self::reading(&result, sample_rate_hz)));
+                    decoded += 1;
+                }
+            })
+        });
+        Self::finish(&mut decoder, decoded, sample_rate_hz, on_result);
+    }
+}
+
+impl PowerProtocol for Em422em {
+    fn name(&self) -> &'static str {
+        "EM422EM"
+    }
+
+    fn radio_profile(&self) -> RadioProfile {
+        Self::PROFILE
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Reading, DecodeError> {
+        let sample_rate_hz = Self::PROFILE.data_rate_baud;
+        let mut decoder = StreamDecoder::new();
+        for &byte in data {
+            if let Some(result) = decoder.push_byte(byte) {
+                return result.map(|result| Self::reading(&result, sample_rate_hz));
+            }
+        }
+        decoder
+            .finish()
+            .map(|result| Self::reading(&result, sample_rate_hz))
+    }
+
+    fn decode_all(&self, data: &[u8], on_result: &mut dyn FnMut(Result<Reading, DecodeError>)) {
+        let sample_rate_hz = Self::PROFILE.data_rate_baud;
+        let mut decoder = StreamDecoder::new();
+        let mut decoded = 0;
+        for &byte in data {
+            if let Some(result) = decoder.push_byte(byte) {
+                on_result(result.map(|result| Self::reading(&result, sample_rate_hz)));
+                decoded += 1;
+            }
+        }
+        Self::finish(&mut decoder, decoded, sample_rate_hz, on_result);
+    }
+
+    fn decode_chunk(
+        &self,
+        stream: &mut CaptureStream,
+        chunk: &[u8],
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        let sample_rate_hz = Self::PROFILE.data_rate_baud;
+        for &byte in chunk {
+            if let Some(result) = stream.decoder.push_byte(byte) {
+                on_result(result.map(|result| Self::reading(&result, sample_rate_hz)));
+                stream.decoded += 1;
+            }
+        }
+    }
+
+    fn finish_stream(
+        &self,
+        stream: &mut CaptureStream,
+        on_result: &mut dyn FnMut(Result<Reading, DecodeError>),
+    ) {
+        let sample_rate_hz = Self::PROFILE.data_rate_baud;
+        Self::finish(
+            &mut stream.decoder,
+            stream.decoded,
+            sample_rate_hz,
+            on_result,
+        );
+    }
+
+    fn repair(&self, error: &DecodeError, plausible: &dyn Fn(&Reading) -> bool) -> Option<Reading> {
+        let result = decode::repair(error, |fields| {
+            plausible(&Reading {
+                protocol: self.name(),
+                sensor_id: fields.transmitter_id,
+                power_kw: fields.power_kw(&Calibration::DEFAULT),
+                quality: 0,
+                corrected_bits: 0,
+                baud_rate: None,
+                energy_kwh: None,
+            })
+        })?;
+        Some(Self::reading(&result, Self::PROFILE.data_rate_baud))
+    }
+
+    fn claimed_sensor_id(&self, error: &DecodeError) -> Option<u32> {
+        match error {
+            DecodeError::ChecksumMismatch { packet, .. } => {
+                Some(Em422Packet::from_bytes(packet).transmitter_id)
+            }
+            _ => None,
+        }
+    }
+
+    fn encode(&self, reading: &Reading, out: &mut [u8]) -> Option<usize> {
+        let packet = Em422Packet::for_power(reading.sensor_id, reading.power_kw)?;
+        decode::encode_packet(&packet.to_bytes(), out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn reading(sensor_id: u32, power_kw: f32) -> Reading {
+        Reading {
+            protocol: "EM422EM",
+            sensor_id,
+            power_kw,
+            quality: 0,
+            corrected_bits: 0,
+            baud_rate: None,
+            energy_kwh: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_em422em_readings() {
+        let mut buf = [0u8; 2 * decode::ENCODED_LEN];
+        let len = Em422em.encode(&reading(0x099B2E, 1.2), &mut buf).unwrap();
+        let len = len
+            + Em422em
+                .encode(&reading(0x111111, 0.3), &mut buf[len..])
+                .unwrap();
+
+        let decoded = Em422em.decode(&buf[..len]).unwrap();
+        assert_eq!(decoded.sensor_id, 0x099B2E);
+        assert!((decoded.power_kw - 1.2).abs() < 1e-3);
+        assert!(decoded.baud_rate.is_some());
+
+        let mut ids = std::vec::Vec::new();
+        Em422em.decode_all(&buf[..len], &mut |result| {
+            ids.push(result.map(|reading| reading.sensor_id))
+        });
+        assert_eq!(ids, [Ok(0x099B2E), Ok(0x111111)]);
+    }
+
+    /// Feeds `capture` to `protocol` a FIFO's worth at a time.
+    fn stream(
+        protocol: &dyn PowerProtocol,
+        capture: &[u8],
+    ) -> std::vec::Vec<Result<u32, DecodeError>> {
+        let mut results = std::vec::Vec::new();
+        let mut on_result =
+            |res: Result<Reading, DecodeError>| results.push(res.map(|reading| reading.sensor_id));
+        let mut stream = CaptureStream::new();
+        for chunk in capture.chunks(60) {
+            protocol.decode_chunk(&mut stream, chunk, &mut on_result);
+        }
+        protocol.finish_stream(&mut stream, &mut on_result);
+        results
+    }
+
+    #[test]
+    fn decodes_captures_as_they_arrive() {
+        // Longer than any buffer, so nothing decodes unless it streams
+        let mut buf = [0u8; 12 * decode::ENCODED_LEN];
+        let mut len = 0;
+        for id in 1..=12 {
+            len += Em422em.encode(&reading(id, 0.3), &mut buf[len..]).unwrap();
+        }
+        let ids: std::vec::Vec<_> = (1..=12).map(Ok).collect();
+        assert_eq!(stream(&Em422em, &buf[..len]), ids);
+        assert_eq!(
+            stream(&Em422em, &[0u8; 300]),
+            [Err(DecodeError::PreambleNotFound)]
+        );
+
+        // Buffered protocols only report an error once nothing decoded
+        let mut frame = std::vec![0u8; 3 * STREAM_BUF_LEN];
+        frame.extend(efergy_frame());
+        assert_eq!(stream(&Efergy, &frame), [Ok(0x0A1234)]);
+        assert_eq!(
+            stream(&Efergy, &[0u8; 300]),
+            [Err(DecodeError::SyncNotFound)]
+        );
+    }
+
+    /// An Efergy frame for transmitter 0A1234, as the radio samples it.
+    fn efergy_frame() -> std::vec::Vec<u8> {
+        let packet = [0x0A, 0x12, 0x34, 0x40, 0x40, 0x00, 0x02, 0xD2];
+        fixtures::pack(&fixtures::pulse_width_bits(&packet), false)
+    }
+
+    #[test]
+    fn shares_listens_between_matching_profiles() {
+        let em422em = Em422em.radio_profile();
+        assert!(em422em.shares_channel(&Efergy.radio_profile()));
+        assert!(!em422em.shares_channel(&Owl.radio_profile()));
+        assert!(!Owl
+            .radio_profile()
+            .shares_channel(&CurrentCost.radio_profile()));
+        assert_eq!(protocol_named("efergy").map(|p| p.name()), Some("Efergy"));
+    }
+
+    #[test]
+    fn repairs_through_the_protocol() {
+        let mut buf = [0u8; decode::ENCODED_LEN];
+        let len = Em422em.encode(&reading(0x099B2E, 0.5), &mut buf).unwrap();
+        // The mantissa is 0x855C. Even out the runs of bit 44, a one, so it
+        // reads as an uncertain zero
+        let idx = 64 + 40 + 16 + 44 * 6 + 2;
+        buf[idx / 8] &= !(0x80 >> (idx % 8));
+
+        let error = Em422em.decode(&buf[..len]).unwrap_err();
+        assert_eq!(Em422em.claimed_sensor_id(&error), Some(0x099B2E));
+        let repaired = Em422em
+            .repair(&error, &|reading| reading.sensor_id == 0x099B2E)
+            .expect("repaired");
+        assert_eq!(repaired.corrected_bits, 1);
+        assert!((repaired.power_kw - 0.5).abs() < 1e-3);
+    }
+}
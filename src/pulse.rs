@@ -0,0 +1,278 @@
+#[cfg(any(test, target_os = "none"))]
+use core::cell::UnsafeCell;
+#[cfg(any(test, target_os = "none"))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(any(test, target_os = "none"))]
+use crate::decode::bit_at;
+
+/// One level of the demodulated signal and how long it lasted, as GDO0
+/// timings in asynchronous serial mode or a Sub-GHz RAW recording give it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    pub level: bool,
+    pub duration_us: u32,
+}
+
+/// Samples each preamble run becomes when resampling, the same as the packet
+/// engine captures at twice the symbol rate.
+pub const PREAMBLE_RUN_SAMPLES: u32 = 2;
+/// Pulses in a row of about the same length that count as a preamble.
+const PREAMBLE_MIN_PULSES: usize = 8;
+/// Longest run emitted for one pulse, so an idle gap doesn't flood the decoder.
+const MAX_PULSE_SAMPLES: u32 = 64;
+/// Pulses a `PulseRing` keeps. A packet and its preamble come to about 170.
+#[cfg(any(test, target_os = "none"))]
+pub const MAX_PULSES: usize = 256;
+
+/// Measures the preamble as pulses stream past: the longest streak of at
+/// least `PREAMBLE_MIN_PULSES` where the longest pulse is within half again of
+/// the shortest. Payload symbols mix runs of one and two preamble runs, so they
+/// don't qualify, and noise rarely keeps a steady length for long.
+#[derive(Debug, Clone, Copy)]
+pub struct PreambleFinder {
+    shortest: u32,
+    longest: u32,
+    total: u32,
+    streak: usize,
+    best: Option<(usize, u32)>,
+}
+
+impl Default for PreambleFinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreambleFinder {
+    pub const fn new() -> Self {
+        Self {
+            shortest: u32::MAX,
+            longest: 0,
+            total: 0,
+            streak: 0,
+            best: None,
+        }
+    }
+
+    pub fn push(&mut self, pulse: Pulse) {
+        let duration_us = pulse.duration_us;
+        let (low, high) = (
+            self.shortest.min(duration_us),
+            self.longest.max(duration_us),
+        );
+        if low == 0 || high > low + low / 2 {
+            // This pulse starts the next streak
+            (self.shortest, self.longest, self.total, self.streak) =
+                (duration_us, duration_us, 0, 0);
+        } else {
+            (self.shortest, self.longest) = (low, high);
+        }
+        self.total = self.total.saturating_add(duration_us);
+        self.streak += 1;
+        if self.streak >= PREAMBLE_MIN_PULSES
+            && self.best.is_none_or(|(longest, _)| self.streak > longest)
+        {
+            self.best = Some((self.streak, self.total));
+        }
+    }
+
+    /// Mean length of one preamble run, if a preamble has gone past.
+    pub fn run_us(&self) -> Option<f32> {
+        self.best
+            .map(|(streak, total)| total as f32 / streak as f32)
+    }
+}
+
+/// Turns pulses into a bit stream sampled every `sample_us`, as the packet
+/// engine would have captured it. Rounding error carries from pulse to pulse
+/// so long captures keep time, and pulses shorter than half a sample vanish.
+#[derive(Debug, Clone, Copy)]
+pub struct Resampler {
+    sample_us: f32,
+    carry: f32,
+}
+
+impl Resampler {
+    pub const fn new(sample_us: f32) -> Self {
+        Self {
+            sample_us,
+            carry: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, pulse: Pulse, mut on_bit: impl FnMut(u8)) {
+        let samples = pulse.duration_us as f32 / self.sample_us + self.carry;
+        let mut count = (samples + 0.5) as u32;
+        self.carry = samples - count as f32;
+        if count > MAX_PULSE_SAMPLES {
+            count = MAX_PULSE_SAMPLES;
+            self.carry = 0.0;
+        }
+        for _ in 0..count {
+            on_bit(pulse.level as u8);
+        }
+    }
+}
+
+/// Run-length encodes a capture oversampled every `sample_us`, MSB first, as
+/// the reverse of `Resampler`. Edges are placed by bit count, so rounding to
+/// whole microseconds doesn't accumulate.
+#[cfg(any(test, target_os = "none"))]
+pub fn bit_pulses(buf: &[u8], sample_us: f32, mut on_pulse: impl FnMut(Pulse)) {
+    let edge_us = |bit_idx: usize| (bit_idx as f32 * sample_us + 0.5) as u32;
+    let bits = buf.len() * 8;
+    let mut start = 0;
+    for bit_idx in 1..=bits {
+        if bit_idx == bits || bit_at(buf, bit_idx) != bit_at(buf, start) {
+            on_pulse(Pulse {
+                level: bit_at(buf, start) == 1,
+                duration_us: edge_us(bit_idx) - edge_us(start),
+            });
+            start = bit_idx;
+        }
+    }
+}
+
+/// Pulses recorded from interrupt context, keeping the latest `MAX_PULSES`.
+#[cfg(any(test, target_os = "none"))]
+pub struct PulseRing {
+    pulses: UnsafeCell<[Pulse; MAX_PULSES]>,
+    written: AtomicUsize,
+}
+
+// Only the capture interrupt pushes, and the ring is only read once it's stopped
+#[cfg(any(test, target_os = "none"))]
+unsafe impl Sync for PulseRing {}
+
+#[cfg(any(test, target_os = "none"))]
+impl PulseRing {
+    pub const fn new() -> Self {
+        Self {
+            pulses: UnsafeCell::new(
+                [Pulse {
+                    level: false,
+                    duration_us: 0,
+                }; MAX_PULSES],
+            ),
+            written: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a pulse, overwriting the oldest once full. Only one context may
+    /// push at a time.
+    pub fn push(&self, pulse: Pulse) {
+        let written = self.written.load(Ordering::Relaxed);
+        unsafe { (*self.pulses.get())[written % MAX_PULSES] = pulse };
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    pub fn clear(&mut self) {
+        self.written.store(0, Ordering::Relaxed);
+    }
+
+    /// The pulses kept, oldest first.
+    pub fn ordered(&mut self) -> &[Pulse] {
+        let written = self.written.load(Ordering::Acquire);
+        let pulses = self.pulses.get_mut();
+        if written <= MAX_PULSES {
+            return &pulses[..written];
+        }
+        pulses.rotate_left(written % MAX_PULSES);
+        self.written.store(MAX_PULSES, Ordering::Relaxed);
+        &pulses[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{self, Em422Packet};
+    use crate::protocol::Em422em;
+
+    /// Pulses for an oversampled capture at `sample_us` per bit, with every
+    /// other edge `jitter_us` late.
+    fn pulses(buf: &[u8], sample_us: f32, jitter_us: i32) -> std::vec::Vec<Pulse> {
+        let mut pulses: std::vec::Vec<Pulse> = std::vec::Vec::new();
+        for bit_idx in 0..buf.len() * 8 {
+            let level = bit_at(buf, bit_idx) == 1;
+            match pulses.last_mut() {
+                Some(last) if last.level == level => last.duration_us += 1,
+                _ => pulses.push(Pulse {
+                    level,
+                    duration_us: 1,
+                }),
+            }
+        }
+        for (idx, pulse) in pulses.iter_mut().enumerate() {
+            let jitter = if idx % 2 == 0 { jitter_us } else { -jitter_us };
+            pulse.duration_us = (pulse.duration_us as f32 * sample_us) as u32;
+            pulse.duration_us = pulse.duration_us.saturating_add_signed(jitter);
+        }
+        pulses
+    }
+
+    fn preamble_run_us(pulses: &[Pulse]) -> Option<f32> {
+        let mut finder = PreambleFinder::new();
+        pulses.iter().for_each(|&pulse| finder.push(pulse));
+        finder.run_us()
+    }
+
+    #[test]
+    fn measures_preamble_runs() {
+        let mut buf = [0u8; decode::ENCODED_LEN];
+        decode::encode_packet(&[0x09, 0x9B, 0x2E, 0x40, 0x12, 0x34, 0x56, 0x78], &mut buf);
+        let mut capture = std::vec![
+            Pulse {
+                level: true,
+                duration_us: 20,
+            };
+            10
+        ];
+        capture.extend(pulses(&buf, 31.0, 3));
+        let run_us = preamble_run_us(&capture).unwrap();
+        assert!((run_us - 62.0).abs() < 0.5, "{run_us}");
+        assert_eq!(preamble_run_us(&pulses(&buf[8..], 31.0, 0)), None);
+    }
+
+    #[test]
+    fn keeps_latest_pulses() {
+        let mut ring = PulseRing::new();
+        for duration_us in 0..MAX_PULSES as u32 + 10 {
+            ring.push(Pulse {
+                level: true,
+                duration_us,
+            });
+        }
+        let ordered = ring.ordered();
+        assert_eq!(ordered.len(), MAX_PULSES);
+        assert_eq!(ordered[0].duration_us, 10);
+        assert_eq!(ordered[MAX_PULSES - 1].duration_us, MAX_PULSES as u32 + 9);
+
+        ring.clear();
+        ring.push(Pulse {
+            level: false,
+            duration_us: 5,
+        });
+        assert_eq!(ring.ordered().len(), 1);
+    }
+
+    #[test]
+    fn decodes_pulses_at_another_rate() {
+        let packet = Em422Packet::for_power(0x099B2E, 1.2).unwrap().to_bytes();
+        let mut buf = [0u8; decode::ENCODED_LEN];
+        decode::encode_packet(&packet, &mut buf);
+
+        // Sampled at 20 kHz rather than the radio's 32.3 kHz
+        let mut readings = std::vec::Vec::new();
+        Em422em.decode_pulses(&pulses(&buf, 50.0, 6), &mut |result| {
+            readings.push(result.unwrap())
+        });
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].sensor_id, 0x099B2E);
+        assert!((readings[0].power_kw - 1.2).abs() < 1e-3);
+        // Six samples a symbol at 20 kHz
+        let baud_rate = readings[0].baud_rate.unwrap();
+        assert!((3_000.0..3_500.0).contains(&baud_rate), "{baud_rate}");
+    }
+}
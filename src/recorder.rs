@@ -0,0 +1,79 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::decode::DecodeError;
+
+/// One capture as the radio handed it over, and what became of it.
+///
+/// Written as a line of text,
+/// `<tick> <frequency Hz> <RSSI dBm> <LQI> <FREQEST> <protocol> <outcome>: <bytes>`,
+/// where the bytes are space separated hex as the `decode.rs` test vectors
+/// take them, and the outcome is `ok` or the name of the decode error.
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureRecord<'a> {
+    pub tick: u32,
+    pub frequency_hz: u32,
+    pub rssi_dbm: f32,
+    pub lqi: u8,
+    /// FREQEST, in FSCTRL0 steps.
+    pub freq_est: i8,
+    pub protocol: &'a str,
+    pub outcome: Result<(), DecodeError>,
+    pub data: &'a [u8],
+}
+
+impl CaptureRecord<'_> {
+    /// Writes the record's line, newline included, passing the text to `out`
+    /// a piece at a time.
+    pub fn write_line(&self, out: &mut dyn FnMut(&str)) {
+        let outcome = match self.outcome {
+            Ok(()) => "ok",
+            Err(error) => error.name(),
+        };
+        let mut text: String<96> = String::new();
+        let _ = write!(
+            text,
+            "{} {} {} {} {} {} {}:",
+            self.tick,
+            self.frequency_hz,
+            self.rssi_dbm as i32,
+            self.lqi,
+            self.freq_est,
+            self.protocol,
+            outcome
+        );
+        out(&text);
+        for byte in self.data {
+            let mut hex: String<4> = String::new();
+            let _ = write!(hex, " {:02X}", byte);
+            out(&hex);
+        }
+        out("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_one_line_per_capture() {
+        let record = CaptureRecord {
+            tick: 81_250,
+            frequency_hz: 433_535_649,
+            rssi_dbm: -71.5,
+            lqi: 12,
+            freq_est: -3,
+            protocol: "EM422EM",
+            outcome: Err(DecodeError::SyncNotFound),
+            data: &[0x33, 0xCC, 0x0F],
+        };
+        let mut line = std::string::String::new();
+        record.write_line(&mut |text| line.push_str(text));
+        assert_eq!(
+            line,
+            "81250 433535649 -71 12 -3 EM422EM sync_not_found: 33 CC 0F\n"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+/// Channels CHANNR can address above the base frequency.
+pub const MAX_SCAN_CHANNELS: usize = 256;
+/// Channel spacings MDMCFG0/1 can express, from datasheet section 21.
+const MIN_SPACING_HZ: u32 = 25_390;
+const MAX_SPACING_HZ: u32 = 405_456;
+
+/// A sweep from `base_hz` in `channels` steps of `spacing_hz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanPlan {
+    pub base_hz: u32,
+    pub spacing_hz: u32,
+    pub channels: u16,
+}
+
+impl ScanPlan {
+    /// Covers `start_hz` to `stop_hz` inclusive. Returns `None` if the range is
+    /// empty, the spacing is out of the CC1101's range, or it needs more
+    /// channels than CHANNR can address.
+    pub fn new(start_hz: u32, stop_hz: u32, spacing_hz: u32) -> Option<Self> {
+        if stop_hz < start_hz || !(MIN_SPACING_HZ..=MAX_SPACING_HZ).contains(&spacing_hz) {
+            return None;
+        }
+        let channels = (stop_hz - start_hz) / spacing_hz + 1;
+        if channels as usize > MAX_SCAN_CHANNELS {
+            return None;
+        }
+        Some(Self {
+            base_hz: start_hz,
+            spacing_hz,
+            channels: channels as u16,
+        })
+    }
+
+    pub fn frequency_hz(&self, channel: u8) -> u32 {
+        self.base_hz + channel as u32 * self.spacing_hz
+    }
+}
+
+/// What was seen while dwelling on one channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelActivity {
+    pub channel: u8,
+    pub peak_rssi_dbm: f32,
+    /// Whether carrier sense asserted at any point.
+    pub carrier: bool,
+    /// Packets the decoder accepted on this channel.
+    pub packets: u32,
+}
+
+/// Where the transmitter most likely sits. Channels that decoded packets win,
+/// and a carrier wide enough to decode on several channels is centred on their
+/// packet-weighted mean. Failing that, the strongest channel with carrier
+/// sense is taken.
+pub fn best_centre_hz(plan: &ScanPlan, results: &[ChannelActivity]) -> Option<u32> {
+    let packets: u32 = results.iter().map(|result| result.packets).sum();
+    if packets > 0 {
+        let weighted: u64 = results
+            .iter()
+            .map(|result| result.packets as u64 * plan.frequency_hz(result.channel) as u64)
+            .sum();
+        return Some((weighted / packets as u64) as u32);
+    }
+
+    results
+        .iter()
+        .filter(|result| result.carrier)
+        .max_by(|a, b| a.peak_rssi_dbm.total_cmp(&b.peak_rssi_dbm))
+        .map(|result| plan.frequency_hz(result.channel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(channel: u8, peak_rssi_dbm: f32, carrier: bool, packets: u32) -> ChannelActivity {
+        ChannelActivity {
+            channel,
+            peak_rssi_dbm,
+            carrier,
+            packets,
+        }
+    }
+
+    #[test]
+    fn plans_channels_across_range() {
+        let plan = ScanPlan::new(433_050_000, 434_790_000, 50_000).unwrap();
+        assert_eq!(plan.channels, 35);
+        assert_eq!(plan.frequency_hz(34), 434_750_000);
+
+        assert!(ScanPlan::new(433_050_000, 434_790_000, 5_000).is_none());
+        assert!(ScanPlan::new(420_000_000, 440_000_000, 30_000).is_none());
+        assert!(ScanPlan::new(434_000_000, 433_000_000, 50_000).is_none());
+    }
+
+    #[test]
+    fn centres_on_decoded_packets() {
+        let plan = ScanPlan::new(433_000_000, 434_000_000, 100_000).unwrap();
+        let results = [
+            activity(4, -50.0, true, 0),
+            activity(5, -60.0, true, 3),
+            activity(6, -62.0, true, 1),
+        ];
+        assert_eq!(best_centre_hz(&plan, &results), Some(433_525_000));
+    }
+
+    #[test]
+    fn falls_back_to_strongest_carrier() {
+        let plan = ScanPlan::new(433_000_000, 434_000_000, 100_000).unwrap();
+        let results = [
+            activity(1, -40.0, false, 0),
+            activity(2, -70.0, true, 0),
+            activity(3, -55.0, true, 0),
+        ];
+        assert_eq!(best_centre_hz(&plan, &results), Some(433_300_000));
+        assert_eq!(best_centre_hz(&plan, &[activity(1, -40.0, false, 0)]), None);
+    }
+}
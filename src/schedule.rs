@@ -0,0 +1,266 @@
+use heapless::Vec;
+
+use crate::protocol::SensorKey;
+
+/// Number of transmitters whose timing is tracked at once.
+pub const MAX_SCHEDULED: usize = 8;
+/// Readings closer together than this are repeats within one transmission.
+const MIN_INTERVAL_MS: u32 = 1_000;
+/// Listen this long either side of a predicted transmission, widened by the
+/// same again for every miss in a row.
+const WINDOW_MARGIN_MS: u32 = 300;
+/// Misses in a row before a transmitter is forgotten, so one that has gone
+/// quiet doesn't keep the receiver waiting on it.
+pub const MAX_MISSES: u8 = 3;
+/// A transmitter heard only once is forgotten after this, so a neighbour's
+/// sensor caught in passing doesn't hold the receiver on.
+const UNLEARNED_TIMEOUT_MS: u32 = 60_000;
+/// Weight of each new period in a transmitter's interval average.
+const INTERVAL_WEIGHT: f32 = 0.25;
+
+/// When to have the receiver on, as `furi_get_tick` values, and for whom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub start_tick: u32,
+    pub end_tick: u32,
+    pub key: SensorKey,
+}
+
+/// Timing learned for one transmitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timing {
+    key: SensorKey,
+    last_tick: u32,
+    interval_ms: Option<f32>,
+    /// Next predicted transmission, once the interval is known.
+    expected_tick: u32,
+    misses: u8,
+}
+
+impl Timing {
+    fn margin_ms(&self) -> u32 {
+        WINDOW_MARGIN_MS * (self.misses as u32 + 1)
+    }
+}
+
+/// Signed ticks from `from` to `to`, allowing for the counter wrapping.
+fn ticks_until(from: u32, to: u32) -> i32 {
+    to.wrapping_sub(from) as i32
+}
+
+/// Learns each transmitter's period and phase from when its packets arrive,
+/// so RX can be powered only around the next expected transmission.
+#[derive(Debug, Clone, Default)]
+pub struct TransmissionSchedule {
+    timings: Vec<Timing, MAX_SCHEDULED>,
+}
+
+impl TransmissionSchedule {
+    pub const fn new() -> Self {
+        Self {
+            timings: Vec::new(),
+        }
+    }
+
+    /// Notes a packet from `key` at `now_tick`. Gaps spanning several periods
+    /// count as missed transmissions rather than one long period.
+    pub fn observe(&mut self, key: SensorKey, now_tick: u32) {
+        let Some(timing) = self.timings.iter_mut().find(|timing| timing.key == key) else {
+            let timing = Timing {
+                key,
+                last_tick: now_tick,
+                interval_ms: None,
+                expected_tick: now_tick,
+                misses: 0,
+            };
+            if let Err(timing) = self.timings.push(timing) {
+                // Replace whichever transmitter has gone quiet longest
+                if let Some(oldest) = self
+                    .timings
+                    .iter_mut()
+                    .max_by_key(|oldest| now_tick.wrapping_sub(oldest.last_tick))
+                {
+                    *oldest = timing;
+                }
+            }
+            return;
+        };
+
+        let elapsed_ms = now_tick.wrapping_sub(timing.last_tick);
+        if elapsed_ms < MIN_INTERVAL_MS {
+            return;
+        }
+        let interval_ms = match timing.interval_ms {
+            Some(interval_ms) => {
+                let periods = (elapsed_ms as f32 / interval_ms + 0.5) as u32;
+                let period_ms = elapsed_ms as f32 / periods.max(1) as f32;
+                interval_ms + (period_ms - interval_ms) * INTERVAL_WEIGHT
+            }
+            None => elapsed_ms as f32,
+        };
+        timing.interval_ms = Some(interval_ms);
+        timing.last_tick = now_tick;
+        timing.expected_tick = now_tick.wrapping_add(interval_ms as u32);
+        timing.misses = 0;
+    }
+
+    /// Counts transmissions whose window has passed unheard, moving their
+    /// prediction on a period, and forgets transmitters missed `MAX_MISSES`
+    /// times in a row.
+    pub fn expire(&mut self, now_tick: u32) {
+        self.timings.retain(|timing| {
+            timing.interval_ms.is_some()
+                || now_tick.wrapping_sub(timing.last_tick) < UNLEARNED_TIMEOUT_MS
+        });
+        for timing in self.timings.iter_mut() {
+            let Some(interval_ms) = timing.interval_ms else {
+                continue;
+            };
+            while timing.misses < MAX_MISSES
+                && ticks_until(timing.expected_tick, now_tick) > timing.margin_ms() as i32
+            {
+                timing.expected_tick = timing.expected_tick.wrapping_add(interval_ms as u32);
+                timing.misses += 1;
+            }
+        }
+        self.timings.retain(|timing| timing.misses < MAX_MISSES);
+    }
+
+    /// The earliest window around a predicted transmission, or `None` if the
+    /// receiver should listen continuously while a transmitter has been heard
+    /// only once, or none at all.
+    pub fn next_window(&self) -> Option<Window> {
+        if self.timings.is_empty()
+            || self
+                .timings
+                .iter()
+                .any(|timing| timing.interval_ms.is_none())
+        {
+            return None;
+        }
+        self.timings
+            .iter()
+            .map(|timing| Window {
+                start_tick: timing.expected_tick.wrapping_sub(timing.margin_ms()),
+                end_tick: timing.expected_tick.wrapping_add(timing.margin_ms()),
+                key: timing.key,
+            })
+            .min_by(|a, b| ticks_until(b.start_tick, a.start_tick).cmp(&0))
+    }
+
+    /// The learned period for `key`, in milliseconds.
+    pub fn interval_ms(&self, key: SensorKey) -> Option<f32> {
+        self.timings
+            .iter()
+            .find(|timing| timing.key == key)
+            .and_then(|timing| timing.interval_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn em(id: u32) -> SensorKey {
+        SensorKey {
+            protocol: "EM422EM",
+            id,
+        }
+    }
+
+    #[test]
+    fn learns_interval_and_phase() {
+        let mut schedule = TransmissionSchedule::new();
+        schedule.observe(em(0x99B2E), 10_000);
+        assert_eq!(schedule.next_window(), None);
+
+        schedule.observe(em(0x99B2E), 10_100);
+        schedule.observe(em(0x99B2E), 16_000);
+        assert_eq!(schedule.interval_ms(em(0x99B2E)), Some(6_000.0));
+        assert_eq!(
+            schedule.next_window(),
+            Some(Window {
+                start_tick: 21_700,
+                end_tick: 22_300,
+                key: em(0x99B2E),
+            })
+        );
+
+        // A transmission lost in between doesn't double the interval
+        schedule.observe(em(0x99B2E), 28_400);
+        assert_eq!(schedule.interval_ms(em(0x99B2E)), Some(6_050.0));
+    }
+
+    #[test]
+    fn forgets_transmitters_after_misses() {
+        let mut schedule = TransmissionSchedule::new();
+        schedule.observe(em(1), 0);
+        schedule.observe(em(1), 5_000);
+
+        schedule.expire(10_400);
+        assert_eq!(
+            schedule.next_window(),
+            Some(Window {
+                start_tick: 14_400,
+                end_tick: 15_600,
+                key: em(1),
+            })
+        );
+        schedule.expire(40_000);
+        assert_eq!(schedule.next_window(), None);
+        assert_eq!(schedule.interval_ms(em(1)), None);
+
+        // Hearing it twice again resumes scheduling
+        schedule.observe(em(1), 40_100);
+        assert_eq!(schedule.next_window(), None);
+        schedule.observe(em(1), 45_100);
+        assert!(schedule.next_window().is_some());
+    }
+
+    #[test]
+    fn keeps_scheduling_around_a_vanished_sensor() {
+        let mut schedule = TransmissionSchedule::new();
+        schedule.observe(em(1), 0);
+        schedule.observe(em(1), 6_000);
+        schedule.observe(em(2), 1_000);
+        schedule.observe(em(2), 8_000);
+
+        // Sensor 2 goes quiet while sensor 1 keeps transmitting
+        for period in 2..10 {
+            schedule.observe(em(1), period * 6_000);
+            schedule.expire(period * 6_000);
+        }
+        assert_eq!(schedule.interval_ms(em(2)), None);
+        assert_eq!(schedule.next_window().map(|window| window.key), Some(em(1)));
+    }
+
+    #[test]
+    fn waits_for_every_sensor_to_be_learned() {
+        let mut schedule = TransmissionSchedule::new();
+        schedule.observe(em(1), 0);
+        schedule.observe(em(1), 6_000);
+        schedule.observe(em(2), u32::MAX - 1_000);
+        assert_eq!(schedule.next_window(), None);
+
+        schedule.observe(em(2), 7_000);
+        // Sensor 2's interval is learned across the tick counter wrapping
+        assert_eq!(schedule.interval_ms(em(2)), Some(8_001.0));
+        let window = schedule.next_window().unwrap();
+        assert_eq!(window.start_tick, 11_700);
+    }
+
+    #[test]
+    fn forgets_transmitters_heard_once() {
+        let mut schedule = TransmissionSchedule::new();
+        schedule.observe(em(1), 0);
+        schedule.observe(em(2), 1_000);
+        for period in 1..11 {
+            schedule.observe(em(1), period * 6_000);
+            schedule.expire(period * 6_000);
+        }
+        assert_eq!(schedule.next_window(), None);
+
+        schedule.expire(1_000 + UNLEARNED_TIMEOUT_MS);
+        assert!(schedule.next_window().is_some());
+    }
+}
@@ -0,0 +1,684 @@
+#[cfg(target_os = "none")]
+use core::ffi::CStr;
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use crate::decode::{self, Calibration};
+#[cfg(target_os = "none")]
+use crate::files;
+use crate::protocol::{self, Reading, SensorKey, PROTOCOLS};
+
+/// Number of transmitters tracked at once.
+pub const MAX_SENSORS: usize = 8;
+pub const SENSOR_NAME_LEN: usize = 16;
+
+#[cfg(target_os = "none")]
+static SENSORS_PATH: &CStr = c"/ext/apps_data/powermon/sensors.txt";
+const SENSORS_FILE_LEN: usize = 1024;
+
+/// Weight of each new estimate in a sensor's frequency offset average.
+const FREQ_OFFSET_WEIGHT: f32 = 0.125;
+
+/// A repaired packet's power may differ from the sensor's last reading by this
+/// much, or by `PLAUSIBLE_STEP_RATIO` of it if that is larger.
+const PLAUSIBLE_STEP_KW: f32 = 0.25;
+const PLAUSIBLE_STEP_RATIO: f32 = 0.5;
+
+/// Everything we know about one transmitter, keyed on its protocol and the ID
+/// in its packet header.
+#[derive(Debug, Clone)]
+pub struct Sensor {
+    pub key: SensorKey,
+    pub name: String<SENSOR_NAME_LEN>,
+    /// Paired sensors are the only ones accepted once any sensor is paired.
+    pub paired: bool,
+    pub calibration: Calibration,
+    /// `furi_get_tick` value when the last good packet arrived.
+    pub last_seen_tick: u32,
+    pub last_power_kw: f32,
+    pub last_rssi_dbm: f32,
+    pub packets_ok: u32,
+    pub packets_failed: u32,
+    /// Packets counted in `packets_ok` that only passed after repair.
+    pub packets_corrected: u32,
+    /// Moving average of the transmitter's carrier offset, in FREQEST steps.
+    pub freq_offset: Option<f32>,
+}
+
+impl Sensor {
+    fn new(key: SensorKey) -> Self {
+        Self {
+            key,
+            name: String::new(),
+            paired: false,
+            calibration: Calibration::DEFAULT,
+            last_seen_tick: 0,
+            last_power_kw: 0.0,
+            last_rssi_dbm: 0.0,
+            packets_ok: 0,
+            packets_failed: 0,
+            packets_corrected: 0,
+            freq_offset: None,
+        }
+    }
+
+    /// Fraction of this sensor's packets that passed the checksum.
+    #[cfg(target_os = "none")]
+    pub fn success_rate(&self) -> f32 {
+        let total = self.packets_ok + self.packets_failed;
+        if total == 0 {
+            return 0.0;
+        }
+        self.packets_ok as f32 / total as f32
+    }
+
+    /// Whether the user has set anything for this sensor that should be saved.
+    pub fn is_configured(&self) -> bool {
+        !self.name.is_empty() || self.paired || self.calibration != Calibration::DEFAULT
+    }
+}
+
+/// Tracks every transmitter heard on the channel.
+#[derive(Debug, Clone)]
+pub struct SensorRegistry {
+    sensors: Vec<Sensor, MAX_SENSORS>,
+    selected: Option<SensorKey>,
+    repair: bool,
+}
+
+impl Default for SensorRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SensorRegistry {
+    pub const fn new() -> Self {
+        Self {
+            sensors: Vec::new(),
+            selected: None,
+            repair: true,
+        }
+    }
+
+    pub fn get(&self, key: SensorKey) -> Option<&Sensor> {
+        self.sensors.iter().find(|sensor| sensor.key == key)
+    }
+
+    /// Reads a sensor named on the command line, as `<protocol>:<hex ID>` or a
+    /// bare ID. Bare IDs mean the sensor already known by that ID, or else an
+    /// EM422EM.
+    pub fn resolve(&self, text: &str) -> Option<SensorKey> {
+        if text.contains(':') {
+            return parse_key(text);
+        }
+        let id = parse_id(text)?;
+        let known = self.sensors.iter().find(|sensor| sensor.key.id == id);
+        Some(known.map_or(default_key(id), |sensor| sensor.key))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Sensor> {
+        self.sensors.iter()
+    }
+
+    /// Returns the sensor with `id`, adding it if needed. When the registry is
+    /// full the least recently seen sensor the user hasn't configured is
+    /// dropped, and if every sensor is configured the new ID is refused.
+    fn entry(&mut self, key: SensorKey) -> Option<&mut Sensor> {
+        if let Some(idx) = self.sensors.iter().position(|sensor| sensor.key == key) {
+            return Some(&mut self.sensors[idx]);
+        }
+
+        if self.sensors.is_full() {
+            let evict = self
+                .sensors
+                .iter()
+                .enumerate()
+                .filter(|(_, sensor)| !sensor.is_configured())
+                .min_by_key(|(_, sensor)| sensor.last_seen_tick)
+                .map(|(idx, _)| idx)?;
+            self.sensors.swap_remove(evict);
+        }
+        let _ = self.sensors.push(Sensor::new(key));
+        self.sensors.last_mut()
+    }
+
+    /// Updates the sensor a successfully decoded packet came from, converting
+    /// the reading with that sensor's calibration. Returns `None` if there is
+    /// no room to track the sensor.
+    pub fn record(&mut self, reading: &Reading, rssi_dbm: f32, now_tick: u32) -> Option<&Sensor> {
+        let sensor = self.entry(reading.key())?;
+        sensor.last_seen_tick = now_tick;
+        sensor.last_power_kw = sensor.calibration.apply(reading.power_kw);
+        sensor.last_rssi_dbm = rssi_dbm;
+        sensor.packets_ok += 1;
+        if reading.is_corrected() {
+            sensor.packets_corrected += 1;
+        }
+        Some(sensor)
+    }
+
+    /// Counts a packet that failed its checksum against the sensor its header
+    /// names. Unknown IDs are ignored since the header itself may be corrupt.
+    pub fn record_failure(&mut self, key: SensorKey) {
+        if let Some(sensor) = self.sensors.iter_mut().find(|sensor| sensor.key == key) {
+            sensor.packets_failed += 1;
+        }
+    }
+
+    /// Folds a carrier offset measured on a good packet into the sensor's
+    /// average, returning the new average. Unknown IDs are ignored.
+    pub fn record_freq_offset(&mut self, key: SensorKey, offset: f32) -> Option<f32> {
+        let sensor = self.sensors.iter_mut().find(|sensor| sensor.key == key)?;
+        let average = match sensor.freq_offset {
+            Some(average) => average + (offset - average) * FREQ_OFFSET_WEIGHT,
+            None => offset,
+        };
+        sensor.freq_offset = Some(average);
+        Some(average)
+    }
+
+    /// Whether a repaired packet is believable: it must come from a sensor that
+    /// has already sent a good packet, with power close to its last reading.
+    pub fn is_plausible(&self, reading: &Reading) -> bool {
+        let Some(sensor) = self.get(reading.key()) else {
+            return false;
+        };
+        if sensor.packets_ok == 0 {
+            return false;
+        }
+        let power_kw = sensor.calibration.apply(reading.power_kw);
+        let max_step_kw = PLAUSIBLE_STEP_KW.max(sensor.last_power_kw * PLAUSIBLE_STEP_RATIO);
+        (power_kw - sensor.last_power_kw).abs() <= max_step_kw
+    }
+
+    /// Whether a sensor can be configured: it is already tracked, or there is
+    /// a free slot or one holding a sensor the user hasn't configured.
+    pub fn has_room_for(&self, key: SensorKey) -> bool {
+        self.get(key).is_some()
+            || !self.sensors.is_full()
+            || self.sensors.iter().any(|sensor| !sensor.is_configured())
+    }
+
+    /// Names a sensor, adding it if it hasn't been heard yet. Returns false if
+    /// the name is too long or there is no room for the sensor.
+    pub fn set_name(&mut self, key: SensorKey, name: &str) -> bool {
+        let mut new_name = String::new();
+        if new_name.push_str(name).is_err() {
+            return false;
+        }
+        self.entry(key)
+            .map(|sensor| sensor.name = new_name)
+            .is_some()
+    }
+
+    /// Returns false if there is no room for the sensor.
+    pub fn set_calibration(&mut self, key: SensorKey, calibration: Calibration) -> bool {
+        self.entry(key)
+            .map(|sensor| sensor.calibration = calibration)
+            .is_some()
+    }
+
+    /// The calibration for `key`, or the default for sensors we don't know.
+    pub fn calibration(&self, key: SensorKey) -> Calibration {
+        self.get(key)
+            .map_or(Calibration::DEFAULT, |sensor| sensor.calibration)
+    }
+
+    /// Applies one `key=value` setting to a sensor, as used in the settings
+    /// file. A value of `none` clears the optional voltage and power factor.
+    pub fn apply_setting(&mut self, key: SensorKey, setting: &str) -> bool {
+        let (name, value) = setting.split_once('=').unwrap_or((setting, ""));
+        let mut calibration = self.calibration(key);
+        match name {
+            "name" => return self.set_name(key, value),
+            "paired" => return self.set_paired(key, true),
+            "scale" => match parse_factor(value) {
+                Some(scale_kw) => calibration.scale_kw = scale_kw,
+                None => return false,
+            },
+            "voltage" => match parse_optional(value, |_| true) {
+                Some(mains_voltage) => calibration.mains_voltage = mains_voltage,
+                None => return false,
+            },
+            "pf" => match parse_optional(value, |pf| pf <= 1.0) {
+                Some(power_factor) => calibration.power_factor = power_factor,
+                None => return false,
+            },
+            _ => return false,
+        }
+        self.set_calibration(key, calibration)
+    }
+
+    /// Pairs or unpairs a sensor, adding it if it hasn't been heard yet.
+    /// Returns false if there is no room for the sensor.
+    pub fn set_paired(&mut self, key: SensorKey, paired: bool) -> bool {
+        self.entry(key)
+            .map(|sensor| sensor.paired = paired)
+            .is_some()
+    }
+
+    /// Whether packets from `key` should be used. Until a sensor is paired
+    /// every transmitter is accepted.
+    pub fn accepts(&self, key: SensorKey) -> bool {
+        let any_paired = self.sensors.iter().any(|sensor| sensor.paired);
+        !any_paired || self.get(key).is_some_and(|sensor| sensor.paired)
+    }
+
+    /// Whether the receiver should listen for `protocol`: once sensors are
+    /// paired, only their protocols are worth the radio's time.
+    pub fn wants_protocol(&self, protocol: &str) -> bool {
+        let any_paired = self.sensors.iter().any(|sensor| sensor.paired);
+        !any_paired
+            || self
+                .sensors
+                .iter()
+                .any(|sensor| sensor.paired && sensor.key.protocol == protocol)
+    }
+
+    /// Limits displayed readings to one sensor, or shows all with `None`.
+    pub fn select(&mut self, key: Option<SensorKey>) {
+        self.selected = key;
+    }
+
+    pub fn selected(&self) -> Option<SensorKey> {
+        self.selected
+    }
+
+    /// The sensor the receiver tunes to: the selected one, or else the only
+    /// paired one. With several candidates FSCTRL0 would chase each in turn.
+    pub fn tuned_sensor(&self) -> Option<SensorKey> {
+        self.selected.or_else(|| {
+            let mut paired = self.sensors.iter().filter(|sensor| sensor.paired);
+            match (paired.next(), paired.next()) {
+                (Some(sensor), None) => Some(sensor.key),
+                _ => None,
+            }
+        })
+    }
+
+    /// Whether packets that fail their checksum get their least certain bits
+    /// flipped to rescue them.
+    pub fn repairs(&self) -> bool {
+        self.repair
+    }
+
+    pub fn set_repair(&mut self, repair: bool) {
+        self.repair = repair;
+    }
+
+    /// Whether readings from `key` should be displayed.
+    pub fn is_shown(&self, key: SensorKey) -> bool {
+        self.selected.is_none_or(|selected| selected == key)
+    }
+
+    /// Restores names and the selection from the settings file format.
+    pub fn load(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("selected") => self.selected = tokens.next().and_then(parse_key),
+                Some("repair") => match tokens.next() {
+                    Some("on") => self.repair = true,
+                    Some("off") => self.repair = false,
+                    _ => {}
+                },
+                Some("sensor") => {
+                    let Some(key) = tokens.next().and_then(parse_key) else {
+                        continue;
+                    };
+                    for token in tokens {
+                        self.apply_setting(key, token);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Writes configured sensors, the selection and whether to repair packets
+    /// in the settings file format.
+    pub fn save(&self, out: &mut impl Write) -> fmt::Result {
+        if let Some(selected) = self.selected {
+            writeln!(out, "selected {}:{:06X}", selected.protocol, selected.id)?;
+        }
+        if !self.repair {
+            writeln!(out, "repair off")?;
+        }
+        for sensor in self.sensors.iter().filter(|sensor| sensor.is_configured()) {
+            write!(out, "sensor {}:{:06X}", sensor.key.protocol, sensor.key.id)?;
+            if !sensor.name.is_empty() {
+                write!(out, " name={}", sensor.name)?;
+            }
+            if sensor.paired {
+                write!(out, " paired")?;
+            }
+            let calibration = &sensor.calibration;
+            if calibration.scale_kw != Calibration::DEFAULT.scale_kw {
+                write!(out, " scale={}", calibration.scale_kw)?;
+            }
+            if let Some(mains_voltage) = calibration.mains_voltage {
+                write!(out, " voltage={}", mains_voltage)?;
+            }
+            if let Some(power_factor) = calibration.power_factor {
+                write!(out, " pf={}", power_factor)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// The SD card is only there on the Flipper.
+#[cfg(target_os = "none")]
+impl SensorRegistry {
+    pub fn load_from_sd(&mut self) {
+        let mut buf = [0u8; SENSORS_FILE_LEN];
+        if let Some(read_bytes) = files::read_file(SENSORS_PATH, &mut buf) {
+            self.load(core::str::from_utf8(&buf[..read_bytes]).unwrap_or(""));
+        }
+    }
+
+    pub fn save_to_sd(&self) -> bool {
+        let mut text: String<SENSORS_FILE_LEN> = String::new();
+        if self.save(&mut text).is_err() {
+            return false;
+        }
+        files::write_file(SENSORS_PATH, text.as_bytes())
+    }
+}
+
+/// Parses a sensor as the settings file writes it, `<protocol>:<hex ID>`. A
+/// bare ID, as files from before other protocols were supported hold, is an
+/// EM422EM.
+pub fn parse_key(text: &str) -> Option<SensorKey> {
+    let Some((protocol, id)) = text.split_once(':') else {
+        return parse_id(text).map(default_key);
+    };
+    Some(SensorKey {
+        protocol: protocol::protocol_named(protocol)?.name(),
+        id: parse_id(id)?,
+    })
+}
+
+fn default_key(id: u32) -> SensorKey {
+    SensorKey {
+        protocol: PROTOCOLS[0].name(),
+        id,
+    }
+}
+
+/// Parses a 24-bit transmitter ID written in hex.
+pub fn parse_id(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16)
+        .ok()
+        .filter(|&id| id <= 0xFF_FFFF)
+}
+
+/// Parses a calibration factor, which must be finite and positive.
+fn parse_factor(text: &str) -> Option<f32> {
+    text.parse()
+        .ok()
+        .filter(|&value| decode::is_positive(value))
+}
+
+/// Parses an optional calibration factor that must also pass `valid`, with
+/// `none` for no value.
+fn parse_optional(text: &str, valid: fn(f32) -> bool) -> Option<Option<f32>> {
+    match text {
+        "none" => Some(None),
+        _ => parse_factor(text).filter(|&value| valid(value)).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::SCALE_KW;
+
+    fn em(id: u32) -> SensorKey {
+        SensorKey {
+            protocol: "EM422EM",
+            id,
+        }
+    }
+
+    /// A reading as an EM422EM packet with exponent 1 would give.
+    fn reading_for(id: u32, mantissa: u16) -> Reading {
+        Reading {
+            protocol: "EM422EM",
+            sensor_id: id,
+            power_kw: SCALE_KW * mantissa as f32 / 32768.0,
+            quality: 255,
+            corrected_bits: 0,
+            baud_rate: None,
+            energy_kwh: None,
+        }
+    }
+
+    #[test]
+    fn tracks_sensors_by_transmitter_id() {
+        let mut registry = SensorRegistry::new();
+        registry.record(&reading_for(0x099B2E, 0x4000), -60.0, 100);
+        registry.record(&reading_for(0x123456, 0x9000), -80.0, 200);
+        registry.record(&reading_for(0x099B2E, 0x8000), -62.0, 300);
+        registry.record_failure(em(0x099B2E));
+        registry.record_failure(em(0xABCDEF));
+
+        assert_eq!(registry.iter().count(), 2);
+        let sensor = registry.get(em(0x099B2E)).unwrap();
+        assert_eq!(sensor.last_seen_tick, 300);
+        assert_eq!(sensor.last_power_kw, SCALE_KW);
+        assert_eq!(sensor.last_rssi_dbm, -62.0);
+        assert_eq!(sensor.packets_ok, 2);
+        assert_eq!(sensor.packets_failed, 1);
+        assert!(registry.get(em(0xABCDEF)).is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_unnamed_sensor() {
+        let mut registry = SensorRegistry::new();
+        registry.set_name(em(0), "Named");
+        for id in 1..MAX_SENSORS as u32 + 1 {
+            registry.record(&reading_for(id, 0x1000), -70.0, id * 10);
+        }
+
+        assert!(registry.get(em(0)).is_some());
+        assert!(registry.get(em(1)).is_none());
+        assert!(registry.get(em(MAX_SENSORS as u32)).is_some());
+    }
+
+    #[test]
+    fn never_evicts_configured_sensors() {
+        let mut registry = SensorRegistry::new();
+        for id in 0..MAX_SENSORS as u32 {
+            assert!(registry.set_paired(em(id), true));
+        }
+        assert!(!registry.has_room_for(em(0xABCDEF)));
+        assert!(!registry.set_name(em(0xABCDEF), "Garage"));
+        assert!(registry
+            .record(&reading_for(0xABCDEF, 0x1000), -70.0, 100)
+            .is_none());
+        assert!(registry.get(em(0xABCDEF)).is_none());
+        assert!((0..MAX_SENSORS as u32).all(|id| registry.get(em(id)).is_some()));
+    }
+
+    #[test]
+    fn only_accepts_paired_sensors_once_paired() {
+        let mut registry = SensorRegistry::new();
+        registry.record(&reading_for(0x123456, 0x9000), -80.0, 200);
+        assert!(registry.accepts(em(0x099B2E)));
+        assert!(registry.accepts(em(0x123456)));
+
+        registry.set_paired(em(0x099B2E), true);
+        assert!(registry.accepts(em(0x099B2E)));
+        assert!(!registry.accepts(em(0x123456)));
+        assert!(!registry.accepts(em(0xABCDEF)));
+    }
+
+    #[test]
+    fn converts_readings_with_sensor_calibration() {
+        let mut registry = SensorRegistry::new();
+        assert!(registry.apply_setting(em(0x099B2E), "scale=0.5"));
+        assert!(registry.apply_setting(em(0x099B2E), "voltage=120"));
+        assert!(registry.apply_setting(em(0x099B2E), "pf=0.9"));
+        assert!(!registry.apply_setting(em(0x099B2E), "pf=high"));
+        for setting in [
+            "scale=0",
+            "scale=-1",
+            "scale=NaN",
+            "scale=inf",
+            "voltage=0",
+            "voltage=-230",
+            "voltage=NaN",
+            "pf=0",
+            "pf=1.5",
+            "pf=NaN",
+        ] {
+            assert!(
+                !registry.apply_setting(em(0x099B2E), setting),
+                "{}",
+                setting
+            );
+        }
+        assert!(registry.apply_setting(em(0x099B2E), "pf=1"));
+        assert!(registry.apply_setting(em(0x099B2E), "pf=0.9"));
+
+        let sensor = registry
+            .record(&reading_for(0x099B2E, 0x8000), -60.0, 100)
+            .unwrap();
+        assert!((sensor.last_power_kw - 0.225).abs() < 1e-6);
+
+        assert!(registry.apply_setting(em(0x099B2E), "voltage=none"));
+        assert_eq!(registry.calibration(em(0x099B2E)).mains_voltage, None);
+    }
+
+    #[test]
+    fn judges_repaired_packets_against_history() {
+        let mut registry = SensorRegistry::new();
+        let unknown = reading_for(0x099B2E, 0x8000);
+        assert!(!registry.is_plausible(&unknown));
+
+        registry.record(&reading_for(0x099B2E, 0x8000), -60.0, 1000);
+        assert!(registry.is_plausible(&reading_for(0x099B2E, 0x9000)));
+        // A flipped exponent or high mantissa bit doubles the reading
+        assert!(!registry.is_plausible(&reading_for(0x099B2E, 0xFFFF)));
+        assert!(!registry.is_plausible(&reading_for(0x099B3E, 0x8000)));
+    }
+
+    #[test]
+    fn averages_frequency_offsets() {
+        let mut registry = SensorRegistry::new();
+        assert_eq!(registry.record_freq_offset(em(0x099B2E), 4.0), None);
+
+        registry.record(&reading_for(0x099B2E, 0x8000), -60.0, 1000);
+        assert_eq!(registry.record_freq_offset(em(0x099B2E), 4.0), Some(4.0));
+        assert_eq!(registry.record_freq_offset(em(0x099B2E), 12.0), Some(5.0));
+        assert_eq!(registry.get(em(0x099B2E)).unwrap().freq_offset, Some(5.0));
+    }
+
+    #[test]
+    fn round_trips_repair_setting() {
+        let mut registry = SensorRegistry::new();
+        assert!(registry.repairs());
+        registry.set_repair(false);
+
+        let mut text: String<SENSORS_FILE_LEN> = String::new();
+        registry.save(&mut text).unwrap();
+        assert_eq!(text.as_str(), "repair off\n");
+        let mut restored = SensorRegistry::new();
+        restored.load(&text);
+        assert!(!restored.repairs());
+        restored.load("repair on\n");
+        assert!(restored.repairs());
+    }
+
+    #[test]
+    fn tunes_to_one_sensor_at_most() {
+        let mut registry = SensorRegistry::new();
+        registry.record(&reading_for(0x099B2E, 0x8000), -60.0, 1000);
+        registry.record(&reading_for(0x123456, 0x8000), -60.0, 1000);
+        assert_eq!(registry.tuned_sensor(), None);
+
+        registry.set_paired(em(0x099B2E), true);
+        assert_eq!(registry.tuned_sensor(), Some(em(0x099B2E)));
+        registry.set_paired(em(0x123456), true);
+        assert_eq!(registry.tuned_sensor(), None);
+
+        registry.select(Some(em(0x123456)));
+        assert_eq!(registry.tuned_sensor(), Some(em(0x123456)));
+    }
+
+    #[test]
+    fn round_trips_settings() {
+        let mut registry = SensorRegistry::new();
+        registry.record(&reading_for(0x123456, 0x9000), -80.0, 200);
+        registry.set_name(em(0x099B2E), "Kitchen");
+        registry.set_paired(em(0x099B2E), true);
+        registry.apply_setting(em(0x099B2E), "voltage=120");
+        registry.select(Some(em(0x099B2E)));
+
+        let mut text: String<SENSORS_FILE_LEN> = String::new();
+        registry.save(&mut text).unwrap();
+        assert_eq!(
+            text.as_str(),
+            "selected EM422EM:099B2E\nsensor EM422EM:099B2E name=Kitchen paired voltage=120\n"
+        );
+
+        let mut restored = SensorRegistry::new();
+        restored.load(&text);
+        assert_eq!(restored.selected(), Some(em(0x099B2E)));
+        assert_eq!(restored.get(em(0x099B2E)).unwrap().name.as_str(), "Kitchen");
+        assert!(restored.get(em(0x099B2E)).unwrap().paired);
+        assert_eq!(
+            restored.calibration(em(0x099B2E)).mains_voltage,
+            Some(120.0)
+        );
+        assert!(restored.get(em(0x123456)).is_none());
+        assert!(restored.is_shown(em(0x099B2E)));
+        assert!(!restored.is_shown(em(0x123456)));
+    }
+
+    #[test]
+    fn keeps_protocols_apart() {
+        let mut registry = SensorRegistry::new();
+        let efergy = Reading {
+            protocol: "Efergy",
+            ..reading_for(0x099B2E, 0x4000)
+        };
+        registry.record(&reading_for(0x099B2E, 0x8000), -60.0, 100);
+        registry.record(&efergy, -75.0, 200);
+        assert_eq!(registry.iter().count(), 2);
+        assert_eq!(registry.get(em(0x099B2E)).unwrap().last_rssi_dbm, -60.0);
+        assert_eq!(registry.get(efergy.key()).unwrap().last_rssi_dbm, -75.0);
+
+        registry.set_paired(efergy.key(), true);
+        assert!(!registry.accepts(em(0x099B2E)));
+        assert!(registry.wants_protocol("Efergy"));
+        assert!(!registry.wants_protocol("EM422EM"));
+    }
+
+    #[test]
+    fn resolves_sensor_names() {
+        let mut registry = SensorRegistry::new();
+        let efergy = SensorKey {
+            protocol: "Efergy",
+            id: 0x0A1234,
+        };
+        assert_eq!(parse_key("efergy:0A1234"), Some(efergy));
+        assert_eq!(parse_key("0A1234"), Some(em(0x0A1234)));
+        assert_eq!(parse_key("Acme:0A1234"), None);
+        assert_eq!(registry.resolve("0A1234"), Some(em(0x0A1234)));
+
+        registry.set_name(efergy, "Heater");
+        assert_eq!(registry.resolve("0A1234"), Some(efergy));
+        assert_eq!(registry.resolve("EM422EM:0A1234"), Some(em(0x0A1234)));
+
+        // Files written before sensors were keyed by protocol hold bare IDs
+        let mut restored = SensorRegistry::new();
+        restored.load("selected 099B2E\nsensor 099B2E name=Kitchen\n");
+        assert_eq!(restored.selected(), Some(em(0x099B2E)));
+        assert_eq!(restored.get(em(0x099B2E)).unwrap().name.as_str(), "Kitchen");
+    }
+}
@@ -0,0 +1,144 @@
+/// Columns on the Flipper's display, one per bar.
+pub const SPECTRUM_WIDTH: usize = 128;
+/// Height of the bar graph in pixels.
+pub const BAR_HEIGHT: u8 = 32;
+/// Sweeps kept in the waterfall, one pixel row each.
+pub const WATERFALL_ROWS: usize = 24;
+/// RSSI mapped to an empty and a full bar.
+const FLOOR_DBM: f32 = -110.0;
+const CEILING_DBM: f32 = -30.0;
+/// 4x4 ordered dither thresholds, shading waterfall pixels by level on a
+/// monochrome display.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// The latest sweep as bars, over a waterfall of the sweeps before it.
+pub struct Spectrum {
+    levels: [u8; SPECTRUM_WIDTH],
+    /// Rows of packed pixels, MSB leftmost, with `newest` the latest.
+    waterfall: [[u8; SPECTRUM_WIDTH / 8]; WATERFALL_ROWS],
+    newest: usize,
+    sweeps: usize,
+    /// Index of the strongest column in the latest sweep.
+    peak: usize,
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Spectrum {
+    pub const fn new() -> Self {
+        Self {
+            levels: [0; SPECTRUM_WIDTH],
+            waterfall: [[0; SPECTRUM_WIDTH / 8]; WATERFALL_ROWS],
+            newest: 0,
+            sweeps: 0,
+            peak: 0,
+        }
+    }
+
+    /// Bar height for an RSSI reading.
+    pub fn level(rssi_dbm: f32) -> u8 {
+        let fraction = (rssi_dbm - FLOOR_DBM) / (CEILING_DBM - FLOOR_DBM);
+        (fraction.clamp(0.0, 1.0) * BAR_HEIGHT as f32) as u8
+    }
+
+    /// Spreads one sweep's readings across the display, keeping the strongest
+    /// where several land in a column, and scrolls it onto the waterfall.
+    pub fn push_sweep(&mut self, rssi_dbm: &[f32]) {
+        if rssi_dbm.is_empty() {
+            return;
+        }
+        self.levels = [0; SPECTRUM_WIDTH];
+        for (step, &rssi) in rssi_dbm.iter().enumerate() {
+            let first = step * SPECTRUM_WIDTH / rssi_dbm.len();
+            let last = ((step + 1) * SPECTRUM_WIDTH / rssi_dbm.len()).max(first + 1);
+            for level in &mut self.levels[first..last] {
+                *level = (*level).max(Self::level(rssi));
+            }
+        }
+        // Reversed so ties go to the lowest frequency
+        self.peak = (0..SPECTRUM_WIDTH)
+            .rev()
+            .max_by_key(|&x| self.levels[x])
+            .unwrap_or(0);
+
+        self.newest = (self.newest + 1) % WATERFALL_ROWS;
+        self.sweeps += 1;
+        let phase = self.sweeps % 4;
+        let row = &mut self.waterfall[self.newest];
+        *row = [0; SPECTRUM_WIDTH / 8];
+        for (x, &level) in self.levels.iter().enumerate() {
+            let shade = level as u32 * 16 / BAR_HEIGHT as u32;
+            if shade > BAYER_4X4[phase][x % 4] as u32 {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    pub fn bar(&self, x: usize) -> u8 {
+        self.levels[x]
+    }
+
+    /// Column of the strongest reading in the latest sweep.
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Whether the waterfall pixel at `x` is set, `age` sweeps ago. Sweeps
+    /// older than the waterfall holds, or not yet taken, are blank.
+    pub fn waterfall_pixel(&self, age: usize, x: usize) -> bool {
+        if age >= WATERFALL_ROWS || age >= self.sweeps {
+            return false;
+        }
+        let row = &self.waterfall[(self.newest + WATERFALL_ROWS - age) % WATERFALL_ROWS];
+        row[x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_rssi_to_bars() {
+        assert_eq!(Spectrum::level(-120.0), 0);
+        assert_eq!(Spectrum::level(-70.0), BAR_HEIGHT / 2);
+        assert_eq!(Spectrum::level(-10.0), BAR_HEIGHT);
+    }
+
+    #[test]
+    fn spreads_sweeps_across_columns() {
+        let mut spectrum = Spectrum::new();
+        let mut sweep = [-110.0f32; 64];
+        sweep[10] = -30.0;
+        spectrum.push_sweep(&sweep);
+
+        assert_eq!(spectrum.bar(20), BAR_HEIGHT);
+        assert_eq!(spectrum.bar(21), BAR_HEIGHT);
+        assert_eq!(spectrum.bar(22), 0);
+        assert_eq!(spectrum.peak(), 20);
+        // A full bar is solid in the waterfall, and silence is blank
+        assert!(spectrum.waterfall_pixel(0, 20) && spectrum.waterfall_pixel(0, 21));
+        assert!(!spectrum.waterfall_pixel(0, 40));
+    }
+
+    #[test]
+    fn scrolls_the_waterfall() {
+        let mut spectrum = Spectrum::new();
+        let loud = [-30.0f32; 128];
+        let quiet = [-110.0f32; 128];
+        spectrum.push_sweep(&loud);
+        spectrum.push_sweep(&quiet);
+
+        assert!(!spectrum.waterfall_pixel(0, 0));
+        assert!(spectrum.waterfall_pixel(1, 0));
+        assert!(!spectrum.waterfall_pixel(2, 0));
+        for _ in 0..WATERFALL_ROWS {
+            spectrum.push_sweep(&quiet);
+        }
+        assert!(!spectrum.waterfall_pixel(WATERFALL_ROWS - 1, 0));
+    }
+}
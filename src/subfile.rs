@@ -0,0 +1,326 @@
+#[cfg(any(test, target_os = "none"))]
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::pulse::Pulse;
+
+/// Longest header key or value kept. Anything past it is dropped.
+const FIELD_LEN: usize = 48;
+
+/// Timings per `RAW_Data` line, as the Sub-GHz app writes them.
+#[cfg(any(test, target_os = "none"))]
+const TIMINGS_PER_LINE: usize = 512;
+
+/// What a Sub-GHz file's header says about the recording.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubHeader {
+    pub frequency_hz: Option<u32>,
+    pub preset: String<FIELD_LEN>,
+    /// Whether the file holds RAW timings rather than a decoded protocol.
+    pub raw: bool,
+}
+
+impl SubHeader {
+    /// Whether the recording was made with an AM preset, whose timings are
+    /// the carrier switching on and off rather than the FSK data.
+    pub fn is_am(&self) -> bool {
+        self.preset.starts_with("FuriHalSubGhzPresetOok")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Key,
+    Value,
+    RawData,
+}
+
+/// Reads a Flipper Sub-GHz file a chunk at a time, turning its `RAW_Data`
+/// timings into pulses. Positive timings are marks and negative ones spaces,
+/// in microseconds, and timings in a row at the same level are merged.
+#[derive(Debug, Clone)]
+pub struct SubParser {
+    header: SubHeader,
+    field: Field,
+    key: String<FIELD_LEN>,
+    value: String<FIELD_LEN>,
+    /// The timing being read, as (is a space, microseconds so far).
+    timing: Option<(bool, u32)>,
+    pending: Option<Pulse>,
+}
+
+impl Default for SubParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubParser {
+    pub const fn new() -> Self {
+        Self {
+            header: SubHeader {
+                frequency_hz: None,
+                preset: String::new(),
+                raw: false,
+            },
+            field: Field::Key,
+            key: String::new(),
+            value: String::new(),
+            timing: None,
+            pending: None,
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8], on_pulse: &mut dyn FnMut(Pulse)) {
+        for &byte in bytes {
+            self.feed_byte(byte, on_pulse);
+        }
+    }
+
+    /// Flushes the last pulse and returns the header.
+    pub fn finish(mut self, on_pulse: &mut dyn FnMut(Pulse)) -> SubHeader {
+        self.end_line(on_pulse);
+        if let Some(pulse) = self.pending.take() {
+            on_pulse(pulse);
+        }
+        self.header
+    }
+
+    fn feed_byte(&mut self, byte: u8, on_pulse: &mut dyn FnMut(Pulse)) {
+        if byte == b'\n' || byte == b'\r' {
+            return self.end_line(on_pulse);
+        }
+        match self.field {
+            Field::Key if byte == b':' => {
+                self.field = match self.key.trim() {
+                    "RAW_Data" => Field::RawData,
+                    _ => Field::Value,
+                };
+            }
+            Field::Key => {
+                let _ = self.key.push(byte as char);
+            }
+            Field::Value => {
+                let _ = self.value.push(byte as char);
+            }
+            Field::RawData => match (byte, &mut self.timing) {
+                (b'-', None) => self.timing = Some((true, 0)),
+                (b'0'..=b'9', timing) => {
+                    let (_, us) = timing.get_or_insert((false, 0));
+                    *us = us.saturating_mul(10).saturating_add((byte - b'0') as u32);
+                }
+                _ => self.end_timing(on_pulse),
+            },
+        }
+    }
+
+    fn end_line(&mut self, on_pulse: &mut dyn FnMut(Pulse)) {
+        match self.field {
+            Field::RawData => self.end_timing(on_pulse),
+            Field::Value => {
+                let value = self.value.trim();
+                match self.key.trim() {
+                    "Frequency" => self.header.frequency_hz = value.parse().ok(),
+                    "Preset" => {
+                        self.header.preset.clear();
+                        let _ = self.header.preset.push_str(value);
+                    }
+                    "Protocol" => self.header.raw = value == "RAW",
+                    _ => {}
+                }
+            }
+            Field::Key => {}
+        }
+        self.field = Field::Key;
+        self.key.clear();
+        self.value.clear();
+    }
+
+    fn end_timing(&mut self, on_pulse: &mut dyn FnMut(Pulse)) {
+        let Some((space, duration_us)) = self.timing.take() else {
+            return;
+        };
+        match &mut self.pending {
+            Some(pending) if pending.level != space => pending.duration_us += duration_us,
+            pending => {
+                if let Some(pulse) = pending.replace(Pulse {
+                    level: !space,
+                    duration_us,
+                }) {
+                    on_pulse(pulse);
+                }
+            }
+        }
+    }
+}
+
+/// The Sub-GHz app's 2-FSK preset closest to `deviation_hz`.
+#[cfg(any(test, target_os = "none"))]
+pub fn fsk_preset(deviation_hz: f32) -> &'static str {
+    if deviation_hz < 36_000.0 {
+        "FuriHalSubGhzPreset2FSKDev238Async"
+    } else {
+        "FuriHalSubGhzPreset2FSKDev476Async"
+    }
+}
+
+/// Writes a Sub-GHz RAW file of the pulses `replay` gives, passing the text
+/// to `out` a piece at a time.
+#[cfg(any(test, target_os = "none"))]
+pub fn write_sub(
+    frequency_hz: u32,
+    preset: &str,
+    replay: impl FnOnce(&mut dyn FnMut(Pulse)),
+    out: &mut dyn FnMut(&str),
+) {
+    let mut text: String<{ FIELD_LEN * 2 }> = String::new();
+    let _ = write!(
+        text,
+        "Filetype: Flipper SubGhz RAW File\nVersion: 1\nFrequency: {}\n",
+        frequency_hz
+    );
+    out(&text);
+    text.clear();
+    let _ = write!(text, "Preset: {}\nProtocol: RAW\n", preset);
+    out(&text);
+
+    let mut count = 0;
+    replay(&mut |pulse| {
+        if pulse.duration_us == 0 {
+            return;
+        }
+        if count % TIMINGS_PER_LINE == 0 {
+            out(if count == 0 {
+                "RAW_Data:"
+            } else {
+                "\nRAW_Data:"
+            });
+        }
+        let mut timing: String<16> = String::new();
+        let sign = if pulse.level { "" } else { "-" };
+        let _ = write!(timing, " {}{}", sign, pulse.duration_us);
+        out(&timing);
+        count += 1;
+    });
+    if count > 0 {
+        out("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{self, Em422Packet};
+    use crate::protocol::Em422em;
+    use crate::pulse::bit_pulses;
+
+    fn parse(text: &str) -> (SubHeader, std::vec::Vec<Pulse>) {
+        let mut pulses = std::vec::Vec::new();
+        let mut parser = SubParser::new();
+        // Split mid-line, as reading in chunks would
+        for chunk in text.as_bytes().chunks(7) {
+            parser.feed(chunk, &mut |pulse| pulses.push(pulse));
+        }
+        let header = parser.finish(&mut |pulse| pulses.push(pulse));
+        (header, pulses)
+    }
+
+    #[test]
+    fn parses_raw_timings() {
+        let text = "Filetype: Flipper SubGhz RAW File\r\nVersion: 1\r\n\
+                    Frequency: 433920000\r\nPreset: FuriHalSubGhzPreset2FSKDev476Async\r\n\
+                    Protocol: RAW\r\nRAW_Data: 120 -62 60 -64\r\nRAW_Data: -30 500 -12";
+        let (header, pulses) = parse(text);
+        assert_eq!(header.frequency_hz, Some(433_920_000));
+        assert_eq!(header.preset.as_str(), "FuriHalSubGhzPreset2FSKDev476Async");
+        assert!(header.raw);
+
+        let durations: std::vec::Vec<(bool, u32)> = pulses
+            .iter()
+            .map(|pulse| (pulse.level, pulse.duration_us))
+            .collect();
+        assert_eq!(
+            durations,
+            [
+                (true, 120),
+                (false, 62),
+                (true, 60),
+                (false, 94),
+                (true, 500),
+                (false, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_am_presets_apart() {
+        for (preset, am) in [
+            ("FuriHalSubGhzPresetOok270Async", true),
+            ("FuriHalSubGhzPresetOok650Async", true),
+            ("FuriHalSubGhzPreset2FSKDev476Async", false),
+            ("FuriHalSubGhzPresetCustom", false),
+        ] {
+            let text = format!("Filetype: Flipper SubGhz RAW File\nPreset: {preset}\n");
+            assert_eq!(parse(&text).0.is_am(), am, "{preset}");
+        }
+    }
+
+    /// Runs every `.sub` file in `SUB_CAPTURES_DIR`, or `captures/` in the
+    /// crate, through the pulse decoder. Each must decode at least one packet.
+    /// Files named `synthetic_*` come from the encoder, not from a meter.
+    #[test]
+    fn decodes_sub_captures() {
+        let dir = std::env::var("SUB_CAPTURES_DIR")
+            .unwrap_or_else(|_| concat!(env!("CARGO_MANIFEST_DIR"), "/captures").into());
+        let mut files: std::vec::Vec<_> = std::fs::read_dir(&dir)
+            .expect("captures directory")
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sub"))
+            .collect();
+        files.sort();
+        assert!(!files.is_empty(), "no .sub files in {dir}");
+
+        for path in files {
+            let (header, pulses) = parse(&std::fs::read_to_string(&path).unwrap());
+            assert!(header.raw, "{} is not a RAW recording", path.display());
+            assert!(!header.is_am(), "{} was recorded with AM", path.display());
+            let mut readings = std::vec::Vec::new();
+            Em422em.decode_pulses(&pulses, &mut |result| {
+                println!("{}: {:?}", path.display(), result);
+                readings.extend(result.ok());
+            });
+            assert!(!readings.is_empty(), "{} decoded nothing", path.display());
+        }
+    }
+
+    #[test]
+    fn writes_captures_the_parser_reads_back() {
+        let packet = Em422Packet::for_power(0x099B2E, 1.2).unwrap().to_bytes();
+        let mut buf = [0u8; decode::ENCODED_LEN];
+        decode::encode_packet(&packet, &mut buf);
+
+        let mut text = std::string::String::new();
+        write_sub(
+            433_920_000,
+            fsk_preset(42_000.0),
+            |on_pulse| bit_pulses(&buf, 1e6 / 32_300.0, on_pulse),
+            &mut |piece| text.push_str(piece),
+        );
+        assert!(text.starts_with("Filetype: Flipper SubGhz RAW File\nVersion: 1\n"));
+        assert!(text.contains("\nRAW_Data: 62 -62 62 -62 "), "{text}");
+
+        let (header, pulses) = parse(&text);
+        assert_eq!(header.frequency_hz, Some(433_920_000));
+        assert_eq!(header.preset.as_str(), "FuriHalSubGhzPreset2FSKDev476Async");
+        assert!(header.raw);
+        // The whole capture spans 512 samples
+        let total_us: u32 = pulses.iter().map(|pulse| pulse.duration_us).sum();
+        assert_eq!(total_us, (512.0 * 1e6 / 32_300.0 + 0.5) as u32);
+
+        let mut readings = std::vec::Vec::new();
+        Em422em.decode_pulses(&pulses, &mut |result| readings.push(result.unwrap()));
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].sensor_id, 0x099B2E);
+    }
+}